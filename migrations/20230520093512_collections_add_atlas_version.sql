@@ -0,0 +1,7 @@
+-- version of the current static atlas, NULL if none was built yet
+ALTER TABLE collections ADD atlas_version INT DEFAULT NULL;
+
+-- set whenever images change, cleared when the static atlas is regenerated
+ALTER TABLE collections ADD atlas_stale boolean
+    NOT NULL
+    DEFAULT TRUE;
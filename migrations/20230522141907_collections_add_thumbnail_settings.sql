@@ -0,0 +1,4 @@
+-- thumbnail ladder, format and resize settings; empty means defaults
+ALTER TABLE collections ADD thumbnail_settings JSONB
+    NOT NULL
+    DEFAULT '{}'::jsonb;
@@ -0,0 +1,2 @@
+-- how transparency was handled: 0 = opaque, 1 = alpha kept, 2 = flattened onto a background
+ALTER TABLE image_files ADD alpha int NOT NULL DEFAULT 0;
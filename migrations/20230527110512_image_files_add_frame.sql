@@ -0,0 +1,6 @@
+-- index of the frame a file was extracted from, e.g. video keyframes; 0 for everything else
+ALTER TABLE image_files ADD frame int NOT NULL DEFAULT 0;
+
+-- recreate primary key
+ALTER TABLE image_files DROP CONSTRAINT image_files_pkey;
+ALTER TABLE image_files ADD PRIMARY KEY (image_id, width, height, kind, frame);
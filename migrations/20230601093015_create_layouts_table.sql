@@ -0,0 +1,41 @@
+-- bumped whenever images of the collection change, so anything derived from them can be invalidated
+ALTER TABLE collections ADD version INT
+    NOT NULL
+    DEFAULT 0;
+
+-- computed layouts; unsaved ones cache one layout per collection and request, saved ones are
+-- kept as they were until deleted; the request is hashed as JSON text
+CREATE TABLE layouts (
+    id UUID
+        PRIMARY KEY
+        DEFAULT gen_random_uuid(),
+    collection_id UUID
+        NOT NULL,
+    name TEXT
+        DEFAULT NULL,
+    saved BOOLEAN
+        NOT NULL
+        DEFAULT false,
+    request JSONB
+        NOT NULL,
+    request_hash TEXT
+        NOT NULL,
+    -- version of the collection the layout was computed for
+    collection_version INT
+        NOT NULL,
+    -- MessagePack encoded layout response
+    data BYTEA
+        NOT NULL,
+    created_at TIMESTAMP
+        NOT NULL
+        DEFAULT now(),
+
+    CONSTRAINT fk_collection
+        FOREIGN KEY(collection_id)
+        REFERENCES collections(id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
+
+CREATE UNIQUE INDEX layouts_cached ON layouts(collection_id, request_hash) WHERE NOT saved;
+CREATE INDEX layouts_saved ON layouts(collection_id, created_at) WHERE saved;
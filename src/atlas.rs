@@ -1,34 +1,55 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Cursor};
+use std::io::{BufReader, Cursor, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use axum::body::StreamBody;
 use axum::extract::Path;
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::Extension;
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use futures::StreamExt;
 use image::io::Reader as ImageReader;
 use image::ImageBuffer;
+use itertools::Itertools;
 use uuid::Uuid;
 
-use crate::db::{Collection, Db, ImageFileKind};
+use crate::db::{Collection, Db, ImageFileKind, ThumbnailSettings};
 use crate::err::{Error, Result};
-use crate::{get_static_atlas_path, uuid_to_string_serialize, DbExtension, Image, ImageFile};
-
-#[derive(serde::Serialize, Clone)]
+use crate::layout::{compute_layout, LayoutRequest};
+use crate::upload::{generate_thumbnail, largest_that_fits, load_original};
+use crate::{
+	get_static_atlas_dir, get_static_atlas_page_path, get_static_atlas_version_dir,
+	uuid_from_string_deserialize, uuid_to_string, uuid_to_string_serialize, DbExtension, Image,
+	ImageFile, ATLAS_MANIFEST_FILE,
+};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct AtlasMapping {
-	#[serde(serialize_with = "uuid_to_string_serialize")]
+	#[serde(
+		serialize_with = "uuid_to_string_serialize",
+		deserialize_with = "uuid_from_string_deserialize"
+	)]
 	id: Uuid,
 	width: u32,
 	height: u32,
 	x: u32,
 	y: u32,
+	// reason why the image could not be placed into the atlas
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	missing: Option<String>,
 }
 
-#[derive(serde::Serialize)]
-pub struct AtlasResponse {
-	#[serde(with = "serde_bytes")]
-	data: Vec<u8>,
-	mapping: Vec<AtlasMapping>,
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct AtlasMissing {
+	#[serde(
+		serialize_with = "uuid_to_string_serialize",
+		deserialize_with = "uuid_from_string_deserialize"
+	)]
+	id: Uuid,
+	reason: String,
 }
 
 #[derive(serde::Serialize)]
@@ -38,13 +59,28 @@ pub struct AtlasFormat<'a> {
 	mapping: Vec<AtlasMapping>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AtlasPage {
+	width: u32,
+	height: u32,
+	extension: String,
+	mapping: Vec<AtlasMapping>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AtlasManifest {
+	version: i32,
+	pages: Vec<AtlasPage>,
+	missing: Vec<AtlasMissing>,
+}
+
 fn gen_atlas(meta: &[Image], max_size: u32) -> (Vec<AtlasMapping>, (u32, u32)) {
 	let total_area = meta.iter().map(|m| m.height * m.width).sum::<u32>();
 	let row_width = f64::sqrt(total_area as f64).trunc() as u32;
 	let row_width = row_width.min(max_size);
 
 	let mut mapping = vec![];
-	let mut current_meta = &meta[..];
+	let mut current_meta = meta;
 	let mut buf_height = 0;
 	let mut buf_width = row_width;
 
@@ -71,7 +107,7 @@ fn gen_atlas(meta: &[Image], max_size: u32) -> (Vec<AtlasMapping>, (u32, u32)) {
 		current_meta = &current_meta[row.len()..];
 
 		// break if row too large or empty
-		if buf_height + height > max_size || row.len() == 0 {
+		if buf_height + height > max_size || row.is_empty() {
 			break;
 		}
 
@@ -84,6 +120,7 @@ fn gen_atlas(meta: &[Image], max_size: u32) -> (Vec<AtlasMapping>, (u32, u32)) {
 				height: m.height,
 				x,
 				y: buf_height,
+				missing: None,
 			});
 
 			x += m.width;
@@ -94,46 +131,73 @@ fn gen_atlas(meta: &[Image], max_size: u32) -> (Vec<AtlasMapping>, (u32, u32)) {
 	(mapping, (buf_width, buf_height))
 }
 
+// load the image of an atlas mapping, on failure the reason it is missing is returned
+async fn load_atlas_image(
+	db: &Db,
+	m: &AtlasMapping,
+) -> Result<std::result::Result<image::DynamicImage, String>> {
+	// look for a thumbnail first
+	let image_entry =
+		ImageFile::get_by_id(db, m.id, m.width, m.height, ImageFileKind::Thumbnail).await?;
+
+	if let Some(image_entry) = image_entry {
+		let path = image_entry.get_path();
+
+		// read file in background task
+		let img = tokio::task::spawn_blocking(move || {
+			let reader = ImageReader::new(BufReader::new(File::open(&path)?));
+			Ok::<_, Error>(reader.with_guessed_format()?.decode()?)
+		})
+		.await?;
+
+		match img {
+			Ok(img) => return Ok(Ok(img)),
+			Err(e) => log::warn!("could not read image file of {}: {}", m.id, e),
+		}
+	}
+
+	let img = match load_original(db, m.id).await {
+		Ok(img) => img,
+		Err(Error::DbError(e)) => return Err(e.into()),
+		Err(e) => return Ok(Err(format!("could not read original: {}", e))),
+	};
+
+	// thumbnail missing or unreadable; generate it from the original
+	match generate_thumbnail(db, m.id, img, m.width, m.height).await {
+		Ok(img) => Ok(Ok(img)),
+		Err(Error::DbError(e)) => Err(e.into()),
+		Err(e) => Ok(Err(format!("could not generate thumbnail: {}", e))),
+	}
+}
+
 async fn build_atlas(
 	db: &Db,
-	mapping: &[AtlasMapping],
+	mapping: &mut [AtlasMapping],
 	width: u32,
 	height: u32,
-) -> Result<image::RgbaImage> {
+) -> Result<(image::RgbaImage, bool)> {
 	// construct image buffer and copy resized images into it
 	let mut img_atlas: image::RgbaImage = ImageBuffer::new(width, height);
+	let has_alpha = AtomicBool::new(false);
+	let has_alpha = &has_alpha;
 
 	let img_atlas_mutex = Arc::new(futures::lock::Mutex::new(&mut img_atlas));
 	let iter_future = mapping
-		.iter()
+		.iter_mut()
 		.map(|m| (m, img_atlas_mutex.clone(), db.clone()))
 		.map(|(m, image_atlas, db)| async move {
-			// load image entry from db
-			let image_entry =
-				match ImageFile::get_by_id(&db, m.id, m.width, m.height, ImageFileKind::Thumbnail)
-					.await?
-				{
-					None => return Ok::<(), Error>(()), // @TODO handle missing image entry
-					Some(s) => s,
-				};
-
-			// load and resize image to the given bounds
-			let path = image_entry.get_path();
-
-			// read file in background task
-			let img = tokio::task::spawn_blocking(move || {
-				let file = File::open(&path)?; // @TODO: handle open error
-				let reader = BufReader::new(file);
-				let img = ImageReader::with_format(reader, image::ImageFormat::Jpeg).decode()?;
-				return Ok::<_, Error>(img);
-			})
-			.await?;
+			let img = match load_atlas_image(&db, m).await? {
+				Ok(img) => img,
+				Err(reason) => {
+					log::warn!("image {} missing from atlas: {}", m.id, reason);
+					m.missing = Some(reason);
+					return Ok::<(), Error>(());
+				}
+			};
 
-			let img = match img {
-				Err(Error::ImageError(_)) => return Ok(()),
-				Err(err) => return Err(err),
-				Ok(ok) => Ok::<_, Error>(ok),
-			}?;
+			if img.color().has_alpha() {
+				has_alpha.store(true, Ordering::Relaxed);
+			}
 
 			// lock underlying data and write to it
 			let mut img_atlas = image_atlas.lock().await;
@@ -146,51 +210,102 @@ async fn build_atlas(
 
 	futures_util::future::try_join_all(iter_future).await?;
 
-	Ok(img_atlas)
+	Ok((img_atlas, has_alpha.load(Ordering::Relaxed)))
 }
 
 const MAX_SIZE: u32 = 4000;
+// lists images of an ordered atlas that could not be placed
+const ATLAS_SKIPPED_HEADER: HeaderName = HeaderName::from_static("x-atlas-skipped");
 
-pub async fn regenerate_static_atlas(db: &Db, collection_id: Uuid) -> Result<()> {
-	let mut metadata = Image::get_all_for_collection(&db, collection_id).await?;
-
+// replace image dimensions with the dimensions of their smallest version;
+// images without thumbnails get the atlas thumbnail size, generated on demand
+async fn use_smallest_sizes(
+	db: &Db,
+	metadata: &mut [Image],
+	settings: &ThumbnailSettings,
+) -> Result<()> {
 	for meta in metadata.iter_mut() {
-		let image_file = ImageFile::get_smallest(db, meta.id).await?;
-		if let Some(image_file) = image_file {
-			meta.width = image_file.width;
-			meta.height = image_file.height;
+		let size = match ImageFile::get_smallest(db, meta.id).await? {
+			Some(image_file) if matches!(image_file.kind, ImageFileKind::Thumbnail) => {
+				Some((image_file.width, image_file.height))
+			}
+			_ => largest_that_fits((meta.width, meta.height), settings.atlas_size()),
+		};
+
+		if let Some((width, height)) = size {
+			meta.width = width;
+			meta.height = height;
 		}
 	}
 
-	metadata.sort_unstable_by_key(|m| u32::MAX - m.height);
+	Ok(())
+}
 
+// pages of mappings and their sizes
+type AtlasPages = Vec<(Vec<AtlasMapping>, (u32, u32))>;
+
+// split images into atlas pages, keeping their order
+// images too large for an empty page are skipped and returned as missing
+fn gen_atlas_pages(metadata: &[Image]) -> (AtlasPages, Vec<AtlasMissing>) {
 	let mut mappings = vec![];
+	let mut skipped = vec![];
 	let mut offset = 0;
-	loop {
+	while offset < metadata.len() {
 		let mapping = gen_atlas(&metadata[offset..], MAX_SIZE);
 
 		let size = mapping.0.len();
-		offset += size;
+		if size == 0 {
+			let m = &metadata[offset];
+			log::warn!("image {} too large for an atlas page", m.id);
+			skipped.push(AtlasMissing {
+				id: m.id,
+				reason: format!("{}x{} does not fit into an atlas page", m.width, m.height),
+			});
+			offset += 1;
+			continue;
+		}
 
+		offset += size;
 		mappings.push(mapping);
-		if offset >= metadata.len() {
-			break;
-		}
 	}
 
-	let file = File::create(crate::get_static_atlas_path(collection_id))?;
-	let mut writer = std::io::BufWriter::new(file);
+	(mappings, skipped)
+}
+
+async fn encode_atlas_page(
+	db: &Db,
+	mapping: &mut [AtlasMapping],
+	width: u32,
+	height: u32,
+	img_buf: &mut Vec<u8>,
+) -> Result<image::ImageFormat> {
+	let (img_atlas, has_alpha) = build_atlas(db, mapping, width, height).await?;
+
+	// pages holding transparent images are PNG, JPEG otherwise
+	let (format, output_format) = match has_alpha {
+		true => (image::ImageFormat::Png, image::ImageOutputFormat::Png),
+		false => (
+			image::ImageFormat::Jpeg,
+			image::ImageOutputFormat::Jpeg(255),
+		),
+	};
+
+	img_buf.clear();
+	img_atlas.write_to(&mut Cursor::new(img_buf), output_format)?;
+
+	Ok(format)
+}
+
+async fn write_atlas<W: std::io::Write>(
+	db: &Db,
+	mappings: AtlasPages,
+	mut writer: W,
+) -> Result<()> {
 	rmp::encode::write_array_len(&mut writer, mappings.len() as u32)?;
 
 	let mut img_buf = vec![];
-	for (mapping, (width, height)) in mappings {
-		let img_atlas = build_atlas(&db, &mapping, width, height).await?;
-
-		img_buf.clear();
-		img_atlas.write_to(
-			&mut Cursor::new(&mut img_buf),
-			image::ImageOutputFormat::Jpeg(255),
-		)?;
+	for (mut mapping, (width, height)) in mappings {
+		encode_atlas_page(db, &mut mapping, width, height, &mut img_buf).await?;
 
 		rmp_serde::encode::write_named(
 			&mut writer,
@@ -204,9 +319,181 @@ pub async fn regenerate_static_atlas(db: &Db, collection_id: Uuid) -> Result<()>
 	Ok(())
 }
 
-pub async fn get_static_atlas(
+lazy_static::lazy_static! {
+	// serializes atlas regeneration per collection
+	static ref ATLAS_LOCKS: std::sync::Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<()>>>> =
+		Default::default();
+}
+
+fn atlas_lock(collection_id: Uuid) -> Arc<tokio::sync::Mutex<()>> {
+	ATLAS_LOCKS
+		.lock()
+		.unwrap()
+		.entry(collection_id)
+		.or_default()
+		.clone()
+}
+
+// directory of the current static atlas, if it is complete and up to date
+fn current_static_atlas(collection: &Collection) -> Result<Option<PathBuf>> {
+	let version = match collection.atlas_version {
+		Some(version) if !collection.atlas_stale => version,
+		_ => return Ok(None),
+	};
+
+	let dir = get_static_atlas_version_dir(collection.id, version);
+	match dir.join(ATLAS_MANIFEST_FILE).try_exists()? {
+		true => Ok(Some(dir)),
+		false => Ok(None),
+	}
+}
+
+pub async fn regenerate_static_atlas(db: &Db, collection_id: Uuid) -> Result<PathBuf> {
+	let lock = atlas_lock(collection_id);
+	let _guard = lock.lock().await;
+
+	regenerate_static_atlas_locked(db, collection_id).await
+}
+
+async fn regenerate_static_atlas_locked(db: &Db, collection_id: Uuid) -> Result<PathBuf> {
+	let collection = Collection::get_by_id(db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	// clear the flag before reading images; changes made from here on mark it stale again
+	Collection::set_atlas_stale(db, collection_id, false).await?;
+
+	let version = collection.atlas_version.unwrap_or(0) + 1;
+	let res = write_static_atlas(db, &collection, version).await;
+
+	let dir = match res {
+		Ok(dir) => dir,
+		Err(e) => {
+			Collection::set_atlas_stale(db, collection_id, true).await?;
+			return Err(e);
+		}
+	};
+	Collection::set_atlas_version(db, collection_id, version).await?;
+
+	// remove older versions, keep the previous one for readers still streaming it
+	for entry in std::fs::read_dir(get_static_atlas_dir(collection_id))? {
+		let entry = entry?;
+		let old_version = entry
+			.file_name()
+			.to_str()
+			.and_then(|v| v.parse::<i32>().ok());
+
+		if matches!(old_version, Some(old_version) if old_version < version - 1) {
+			std::fs::remove_dir_all(entry.path())?;
+		}
+	}
+
+	Ok(dir)
+}
+
+async fn write_static_atlas(db: &Db, collection: &Collection, version: i32) -> Result<PathBuf> {
+	let collection_id = collection.id;
+	let mut metadata = Image::get_all_for_collection(db, collection_id).await?;
+	use_smallest_sizes(db, &mut metadata, &collection.thumbnail_settings).await?;
+
+	metadata.sort_unstable_by_key(|m| u32::MAX - m.height);
+
+	// write into a temporary directory, left-overs of a crashed run are overwritten
+	let dir = get_static_atlas_version_dir(collection_id, version);
+	let mut tmp_dir = dir.clone();
+	tmp_dir.set_extension("tmp");
+
+	if tmp_dir.try_exists()? {
+		std::fs::remove_dir_all(&tmp_dir)?;
+	}
+	std::fs::create_dir_all(&tmp_dir)?;
+
+	let mut manifest = AtlasManifest {
+		version,
+		pages: vec![],
+		missing: vec![],
+	};
+	let (pages, skipped) = gen_atlas_pages(&metadata);
+	manifest.missing.extend(skipped);
+
+	let mut img_buf = vec![];
+	for (n, (mut mapping, (width, height))) in pages.into_iter().enumerate() {
+		let format = encode_atlas_page(db, &mut mapping, width, height, &mut img_buf).await?;
+		let extension = format.extensions_str()[0];
+		std::fs::write(get_static_atlas_page_path(&tmp_dir, n, extension), &img_buf)?;
+
+		manifest.missing.extend(mapping.iter().filter_map(|m| {
+			m.missing
+				.clone()
+				.map(|reason| AtlasMissing { id: m.id, reason })
+		}));
+
+		manifest.pages.push(AtlasPage {
+			width,
+			height,
+			extension: extension.to_owned(),
+			mapping,
+		});
+	}
+
+	let file = File::create(tmp_dir.join(ATLAS_MANIFEST_FILE))?;
+	let mut writer = std::io::BufWriter::new(file);
+	rmp_serde::encode::write_named(&mut writer, &manifest)?;
+	writer.flush()?;
+	writer.get_ref().sync_all()?;
+
+	// atomically move the complete atlas into place
+	if dir.try_exists()? {
+		std::fs::remove_dir_all(&dir)?;
+	}
+	std::fs::rename(&tmp_dir, &dir)?;
+
+	Ok(dir)
+}
+
+// make sure the static atlas of a finalized collection is up to date, return its directory
+async fn ensure_static_atlas(db: &Db, collection_id: Uuid) -> Result<PathBuf> {
+	let collection = Collection::get_by_id(db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	if !collection.finalized {
+		return Err(Error::Custom(
+			StatusCode::BAD_REQUEST,
+			"collection not finalized".into(),
+		));
+	}
+
+	if let Some(dir) = current_static_atlas(&collection)? {
+		return Ok(dir);
+	}
+
+	let lock = atlas_lock(collection_id);
+	let _guard = lock.lock().await;
+
+	// another request might have regenerated it while waiting for the lock
+	let collection = Collection::get_by_id(db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	if let Some(dir) = current_static_atlas(&collection)? {
+		return Ok(dir);
+	}
+
+	regenerate_static_atlas_locked(db, collection_id).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum AtlasRequest {
+	Ids(Vec<Uuid>),
+	Layout(Box<LayoutRequest>),
+}
+
+pub async fn get_ordered_atlas(
 	Extension(db): DbExtension,
 	Path(collection_id): Path<Uuid>,
+	Json(req): Json<AtlasRequest>,
 ) -> Result<impl IntoResponse> {
 	let collection = Collection::get_by_id(&db, collection_id)
 		.await?
@@ -219,16 +506,173 @@ pub async fn get_static_atlas(
 		));
 	}
 
-	let path = get_static_atlas_path(collection.id);
-	let exists = path.try_exists()?;
+	let order = match req {
+		// repeated ids are placed once, at their first position
+		AtlasRequest::Ids(ids) => ids.into_iter().unique().collect(),
+		AtlasRequest::Layout(layout) => compute_layout(&db, collection_id, *layout)
+			.await?
+			.layout
+			.order(),
+	};
+
+	// arrange images in the requested order
+	let mut images: HashMap<Uuid, Image> = Image::get_all_for_collection(&db, collection_id)
+		.await?
+		.into_iter()
+		.map(|image| (image.id, image))
+		.collect();
+
+	let mut metadata = order
+		.into_iter()
+		.map(|id| {
+			images
+				.remove(&id)
+				.ok_or(Error::NotFound(format!("image with id {}", id)))
+		})
+		.collect::<Result<Vec<_>>>()?;
+	use_smallest_sizes(&db, &mut metadata, &collection.thumbnail_settings).await?;
+
+	let (pages, skipped) = gen_atlas_pages(&metadata);
+	let mut buf = vec![];
+	write_atlas(&db, pages, &mut buf).await?;
+
+	// ids of images left out of the atlas, comma separated
+	let skipped = skipped.iter().map(|m| uuid_to_string(&m.id)).join(",");
+	Ok(([(ATLAS_SKIPPED_HEADER, skipped)], buf))
+}
+
+pub async fn get_static_atlas(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+	let dir = ensure_static_atlas(&db, collection_id).await?;
+
+	let manifest = tokio::fs::read(dir.join(ATLAS_MANIFEST_FILE)).await?;
+	let manifest: AtlasManifest = rmp_serde::from_slice(&manifest)?;
+
+	// assemble single-file format from manifest and pages, one page at a time
+	let mut header = vec![];
+	rmp::encode::write_array_len(&mut header, manifest.pages.len() as u32)?;
+	let header_stream = futures::stream::once(async move { Ok::<_, Error>(header) });
+
+	let page_stream =
+		futures::stream::iter(manifest.pages.into_iter().enumerate()).then(move |(n, page)| {
+			let path = get_static_atlas_page_path(&dir, n, &page.extension);
+			async move {
+				let data = tokio::fs::read(path).await?;
+
+				let mut buf = vec![];
+				rmp_serde::encode::write_named(
+					&mut buf,
+					&AtlasFormat {
+						data: &data,
+						mapping: page.mapping,
+					},
+				)?;
+
+				Ok(buf)
+			}
+		});
+
+	Ok(StreamBody::new(header_stream.chain(page_stream)))
+}
 
-	if !exists {
-		regenerate_static_atlas(&db, collection_id).await?;
+pub async fn get_static_atlas_manifest(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+	let dir = ensure_static_atlas(&db, collection_id).await?;
+	let manifest = tokio::fs::read(dir.join(ATLAS_MANIFEST_FILE)).await?;
+
+	Ok(manifest)
+}
+
+pub async fn get_static_atlas_page(
+	Extension(db): DbExtension,
+	Path((collection_id, version, n)): Path<(Uuid, i32, usize)>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let dir = ensure_static_atlas(&db, collection_id).await?;
+
+	// pages of older versions are removed on regeneration; clients need to fetch the manifest again
+	if dir.file_name().and_then(|v| v.to_str()) != Some(&version.to_string()) {
+		return Err(Error::Custom(
+			StatusCode::GONE,
+			format!("atlas version {} is outdated", version),
+		));
+	}
+
+	let manifest = tokio::fs::read(dir.join(ATLAS_MANIFEST_FILE)).await?;
+	let manifest: AtlasManifest = rmp_serde::from_slice(&manifest)?;
+	let page = manifest
+		.pages
+		.get(n)
+		.ok_or(Error::NotFound(format!("atlas page {}", n)))?;
+
+	let path = get_static_atlas_page_path(&dir, n, &page.extension);
+	let mime = match page.extension.as_str() {
+		"png" => "image/png",
+		_ => "image/jpeg",
+	};
+
+	// pages never change within an atlas version
+	let etag = format!("\"{}-{}\"", version, n);
+
+	let cache_headers = [
+		(header::ETAG, etag.clone()),
+		(
+			header::CACHE_CONTROL,
+			"public, max-age=31536000, immutable".to_owned(),
+		),
+	];
+
+	let not_modified = headers
+		.get(header::IF_NONE_MATCH)
+		.and_then(|v| v.to_str().ok())
+		.is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+
+	if not_modified {
+		return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
 	}
 
-	let atlas_file = tokio::fs::File::open(path).await?;
-	let reader = tokio::io::BufReader::new(atlas_file);
-	let stream = tokio_util::io::ReaderStream::new(reader);
+	let data = tokio::fs::read(path).await?;
+
+	Ok((cache_headers, [(header::CONTENT_TYPE, mime)], data).into_response())
+}
 
-	Ok(axum::body::StreamBody::new(stream).into_response())
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn manifest_roundtrip() {
+		let id = Uuid::new_v4();
+		let manifest = AtlasManifest {
+			version: 3,
+			pages: vec![AtlasPage {
+				width: 64,
+				height: 32,
+				extension: "jpg".into(),
+				mapping: vec![AtlasMapping {
+					id,
+					width: 32,
+					height: 32,
+					x: 32,
+					y: 0,
+					missing: None,
+				}],
+			}],
+			missing: vec![AtlasMissing {
+				id,
+				reason: "too large".into(),
+			}],
+		};
+
+		let data = rmp_serde::to_vec_named(&manifest).unwrap();
+		let read = rmp_serde::from_slice::<AtlasManifest>(&data).unwrap();
+		assert_eq!(read.version, 3);
+		assert_eq!(read.pages[0].mapping[0].id, id);
+		assert_eq!(read.pages[0].mapping[0].x, 32);
+		assert_eq!(read.missing[0].id, id);
+	}
 }
@@ -0,0 +1,177 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{Exif, In, Tag, Value};
+
+// typed EXIF fields describing how an image was captured
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+pub struct CaptureInfo {
+	pub make: Option<String>,
+	pub model: Option<String>,
+	pub lens: Option<String>,
+	pub iso: Option<u32>,
+	pub f_number: Option<f32>,
+	// seconds
+	pub exposure_time: Option<f32>,
+	// millimetres, and the 35mm film equivalent if the camera reports it
+	pub focal_length: Option<f32>,
+	pub focal_length_35mm: Option<u32>,
+	// local time of capture; the offset to UTC is only recorded by newer cameras
+	pub date_time_original: Option<NaiveDateTime>,
+	pub utc_offset_minutes: Option<i16>,
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+	match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+		Some(Value::Ascii(values)) => values
+			.iter()
+			.map(|v| {
+				String::from_utf8_lossy(v)
+					.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+					.to_owned()
+			})
+			.find(|v| !v.is_empty()),
+		_ => None,
+	}
+}
+
+// zero and undefined values are reported as missing
+fn rational(exif: &Exif, tag: Tag) -> Option<f32> {
+	let value = match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+		Some(Value::Rational(values)) => values.first()?.to_f64(),
+		Some(Value::SRational(values)) => values.first()?.to_f64(),
+		_ => return None,
+	};
+
+	match value.is_finite() && value > 0.0 {
+		true => Some(value as f32),
+		false => None,
+	}
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+	exif.get_field(tag, In::PRIMARY)
+		.and_then(|f| f.value.get_uint(0))
+		.filter(|v| *v > 0)
+}
+
+// local date and time of a DateTime-like tag, along with the offset from its OffsetTime-like tag
+fn date_time(exif: &Exif, tag: Tag, offset_tag: Tag) -> Option<(NaiveDateTime, Option<i16>)> {
+	let data = match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+		Some(Value::Ascii(values)) => values.first()?,
+		_ => return None,
+	};
+
+	let mut dt = exif::DateTime::from_ascii(data).ok()?;
+	if let Some(Value::Ascii(values)) = exif.get_field(offset_tag, In::PRIMARY).map(|f| &f.value) {
+		if let Some(offset) = values.first() {
+			dt.parse_offset(offset).ok();
+		}
+	}
+
+	let date_time = NaiveDate::from_ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)?
+		.and_hms_opt(dt.hour as u32, dt.minute as u32, dt.second as u32)?;
+	Some((date_time, dt.offset))
+}
+
+impl CaptureInfo {
+	pub fn from_exif(exif: &Exif) -> Self {
+		let (date_time_original, utc_offset_minutes) =
+			match date_time(exif, Tag::DateTimeOriginal, Tag::OffsetTimeOriginal) {
+				Some((date_time, offset)) => (Some(date_time), offset),
+				None => (None, None),
+			};
+
+		Self {
+			make: ascii(exif, Tag::Make),
+			model: ascii(exif, Tag::Model),
+			lens: ascii(exif, Tag::LensModel),
+			iso: uint(exif, Tag::PhotographicSensitivity),
+			f_number: rational(exif, Tag::FNumber),
+			exposure_time: rational(exif, Tag::ExposureTime),
+			focal_length: rational(exif, Tag::FocalLength),
+			focal_length_35mm: uint(exif, Tag::FocalLengthIn35mmFilm),
+			date_time_original,
+			utc_offset_minutes,
+		}
+	}
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct GpsPosition {
+	// degrees, north and east are positive
+	pub latitude: f64,
+	pub longitude: f64,
+	// metres above sea level
+	pub altitude: Option<f64>,
+}
+
+// degrees, minutes and seconds to signed degrees
+fn coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+	let dms = match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+		Some(Value::Rational(values)) if !values.is_empty() => values,
+		_ => return None,
+	};
+	let degrees = dms
+		.iter()
+		.take(3)
+		.zip([1.0, 60.0, 3600.0])
+		.map(|(v, div)| v.to_f64() / div)
+		.sum::<f64>();
+
+	let negative = match exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+		Some(Value::Ascii(values)) => values
+			.first()
+			.and_then(|v| v.first())
+			.is_some_and(|c| c.to_ascii_uppercase() == negative_ref),
+		_ => false,
+	};
+
+	match degrees.is_finite() {
+		true if negative => Some(-degrees),
+		true => Some(degrees),
+		false => None,
+	}
+}
+
+impl GpsPosition {
+	pub fn from_exif(exif: &Exif) -> Option<Self> {
+		let latitude = coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+		let longitude = coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+		if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+			return None;
+		}
+
+		let altitude = match exif
+			.get_field(Tag::GPSAltitude, In::PRIMARY)
+			.map(|f| &f.value)
+		{
+			Some(Value::Rational(values)) => values.first().map(|v| v.to_f64()),
+			_ => None,
+		};
+		// reference 1 means below sea level
+		let below_sea_level = exif
+			.get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+			.and_then(|f| f.value.get_uint(0))
+			== Some(1);
+		let altitude = altitude
+			.filter(|a| a.is_finite())
+			.map(|a| if below_sea_level { -a } else { a });
+
+		Some(Self {
+			latitude,
+			longitude,
+			altitude,
+		})
+	}
+}
+
+// when the image was taken, falling back to when it was digitized or last changed
+pub fn read_date_time(exif: &Exif) -> Option<NaiveDateTime> {
+	[
+		(Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+		(Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+		(Tag::DateTime, Tag::OffsetTime),
+	]
+	.into_iter()
+	.find_map(|(tag, offset_tag)| date_time(exif, tag, offset_tag))
+	.map(|(date_time, _)| date_time)
+}
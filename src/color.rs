@@ -0,0 +1,476 @@
+use image::DynamicImage;
+
+// bins per channel of colour histograms
+pub const HISTOGRAM_BINS: usize = 4;
+
+// palette entry in CIELAB (D65), weighted by the fraction of pixels closest to it
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct LabColor {
+	pub l: f32,
+	pub a: f32,
+	pub b: f32,
+	pub weight: f32,
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+	let c = c as f32 / 255.0;
+	match c <= 0.04045 {
+		true => c / 12.92,
+		false => ((c + 0.055) / 1.055).powf(2.4),
+	}
+}
+
+pub fn srgb_to_lab((r, g, b): (u8, u8, u8)) -> (f32, f32, f32) {
+	let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+
+	// relative to the D65 white point
+	let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.950_47;
+	let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+	let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.088_83;
+
+	let f = |t: f32| match t > 216.0 / 24389.0 {
+		true => t.cbrt(),
+		false => (24389.0 / 27.0 * t + 16.0) / 116.0,
+	};
+	let (fx, fy, fz) = (f(x), f(y), f(z));
+
+	(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+// perceptual colour difference; about 1 is just noticeable
+// computed in double precision and degrees as in the reference implementation,
+// otherwise hue differences of exactly 180° end up on either side
+pub fn ciede2000((l1, a1, b1): (f32, f32, f32), (l2, a2, b2): (f32, f32, f32)) -> f32 {
+	let (l1, a1, b1) = (l1 as f64, a1 as f64, b1 as f64);
+	let (l2, a2, b2) = (l2 as f64, a2 as f64, b2 as f64);
+
+	let c1 = (a1 * a1 + b1 * b1).sqrt();
+	let c2 = (a2 * a2 + b2 * b2).sqrt();
+	let c_mean = (c1 + c2) / 2.0;
+	let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+
+	let a1 = a1 * (1.0 + g);
+	let a2 = a2 * (1.0 + g);
+	let c1 = (a1 * a1 + b1 * b1).sqrt();
+	let c2 = (a2 * a2 + b2 * b2).sqrt();
+
+	let hue = |b: f64, a: f64| match b == 0.0 && a == 0.0 {
+		true => 0.0,
+		false => {
+			let h = b.atan2(a).to_degrees();
+			match h < 0.0 {
+				true => h + 360.0,
+				false => h,
+			}
+		}
+	};
+	let h1 = hue(b1, a1);
+	let h2 = hue(b2, a2);
+
+	let dl = l2 - l1;
+	let dc = c2 - c1;
+	let dh = match c1 * c2 == 0.0 {
+		true => 0.0,
+		false => {
+			let dh = h2 - h1;
+			if dh > 180.0 {
+				dh - 360.0
+			} else if dh < -180.0 {
+				dh + 360.0
+			} else {
+				dh
+			}
+		}
+	};
+	let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+	let l_mean = (l1 + l2) / 2.0;
+	let c_mean = (c1 + c2) / 2.0;
+	let h_mean = if c1 * c2 == 0.0 {
+		h1 + h2
+	} else if (h1 - h2).abs() <= 180.0 {
+		(h1 + h2) / 2.0
+	} else if h1 + h2 < 360.0 {
+		(h1 + h2 + 360.0) / 2.0
+	} else {
+		(h1 + h2 - 360.0) / 2.0
+	};
+
+	let cos = |degrees: f64| degrees.to_radians().cos();
+	let t =
+		1.0 - 0.17 * cos(h_mean - 30.0) + 0.24 * cos(2.0 * h_mean) + 0.32 * cos(3.0 * h_mean + 6.0)
+			- 0.20 * cos(4.0 * h_mean - 63.0);
+
+	let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+	let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+	let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+	let s_c = 1.0 + 0.045 * c_mean;
+	let s_h = 1.0 + 0.015 * c_mean * t;
+	let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+	let (dl, dc, dh) = (dl / s_l, dc / s_c, dh / s_h);
+	(dl * dl + dc * dc + dh * dh + r_t * dc * dh).sqrt() as f32
+}
+
+impl LabColor {
+	pub fn lab(&self) -> (f32, f32, f32) {
+		(self.l, self.a, self.b)
+	}
+}
+
+// palette in CIELAB; weights are the fractions of pixels closest to each colour
+pub fn lab_palette(img: &DynamicImage, palette: &[(u8, u8, u8)]) -> Vec<LabColor> {
+	let mut colors = palette
+		.iter()
+		.map(|c| {
+			let (l, a, b) = srgb_to_lab(*c);
+			LabColor {
+				l,
+				a,
+				b,
+				weight: 0.0,
+			}
+		})
+		.collect::<Vec<_>>();
+	if colors.is_empty() {
+		return colors;
+	}
+
+	// the palette is small, so pixels are assigned to their closest colour in sRGB
+	let rgb = img.to_rgb8();
+	let len = rgb.pixels().len().max(1) as f32;
+	for p in rgb.pixels() {
+		let closest = palette
+			.iter()
+			.enumerate()
+			.min_by_key(|(_, c)| {
+				let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+				d(c.0, p.0[0]) + d(c.1, p.0[1]) + d(c.2, p.0[2])
+			})
+			.map(|(i, _)| i)
+			.unwrap();
+		colors[closest].weight += 1.0 / len;
+	}
+
+	colors.sort_unstable_by(|c1, c2| c2.weight.total_cmp(&c1.weight));
+	colors
+}
+
+// fractions of pixels per sRGB bin
+pub fn color_histogram(img: &DynamicImage) -> Vec<f32> {
+	let rgb = img.to_rgb8();
+	let len = rgb.pixels().len().max(1) as f32;
+	let bin = |c: u8| c as usize * HISTOGRAM_BINS / 256;
+
+	let mut histogram = vec![0.0; HISTOGRAM_BINS.pow(3)];
+	for p in rgb.pixels() {
+		let [r, g, b] = p.0;
+		histogram[(bin(r) * HISTOGRAM_BINS + bin(g)) * HISTOGRAM_BINS + bin(b)] += 1.0 / len;
+	}
+
+	histogram
+}
+
+// earth mover's distance between weighted palettes, with CIEDE2000 as ground distance
+// solved exactly as a min-cost flow, palettes are small enough for successive shortest paths
+pub fn palette_emd(p1: &[LabColor], p2: &[LabColor]) -> f32 {
+	const EPSILON: f32 = 1e-6;
+
+	// there is nothing to match colours with
+	if p1.is_empty() || p2.is_empty() {
+		return f32::INFINITY;
+	}
+
+	// weights may not add up to exactly the same amount; only the common mass is moved
+	let total1 = p1.iter().map(|c| c.weight).sum::<f32>();
+	let total2 = p2.iter().map(|c| c.weight).sum::<f32>();
+	let mut supply = p1.iter().map(|c| c.weight / total1).collect::<Vec<_>>();
+	let mut demand = p2.iter().map(|c| c.weight / total2).collect::<Vec<_>>();
+	if supply.iter().any(|s| !s.is_finite()) || demand.iter().any(|d| !d.is_finite()) {
+		return f32::INFINITY;
+	}
+
+	let cost = p1
+		.iter()
+		.map(|c1| {
+			p2.iter()
+				.map(|c2| ciede2000(c1.lab(), c2.lab()))
+				.collect::<Vec<_>>()
+		})
+		.collect::<Vec<_>>();
+	let mut flow = vec![vec![0f32; p2.len()]; p1.len()];
+
+	let mut total_cost = 0.0;
+	loop {
+		// Bellman-Ford over the residual graph: suppliers 0..n, consumers n..n+m
+		let (n, m) = (p1.len(), p2.len());
+		let mut dist = vec![f32::INFINITY; n + m];
+		let mut prev = vec![usize::MAX; n + m];
+		for (i, s) in supply.iter().enumerate() {
+			if *s > EPSILON {
+				dist[i] = 0.0;
+			}
+		}
+		for _ in 0..n + m {
+			let mut changed = false;
+			for i in 0..n {
+				for j in 0..m {
+					// forward edges are unbounded, backward edges hold the flow so far
+					if dist[i] + cost[i][j] < dist[n + j] - EPSILON {
+						dist[n + j] = dist[i] + cost[i][j];
+						prev[n + j] = i;
+						changed = true;
+					}
+					if flow[i][j] > EPSILON && dist[n + j] - cost[i][j] < dist[i] - EPSILON {
+						dist[i] = dist[n + j] - cost[i][j];
+						prev[i] = n + j;
+						changed = true;
+					}
+				}
+			}
+			if !changed {
+				break;
+			}
+		}
+
+		// cheapest consumer that still has demand
+		let target = (0..m)
+			.filter(|j| demand[*j] > EPSILON && dist[n + j].is_finite())
+			.min_by(|a, b| dist[n + a].total_cmp(&dist[n + b]));
+		let target = match target {
+			Some(j) => n + j,
+			None => break,
+		};
+
+		// walk back to the supplier, finding how much can be moved along the path
+		let mut path = vec![target];
+		let mut node = target;
+		while prev[node] != usize::MAX {
+			node = prev[node];
+			path.push(node);
+		}
+		let source = node;
+
+		let mut amount = supply[source].min(demand[target - n]);
+		for edge in path.windows(2) {
+			let (to, from) = (edge[0], edge[1]);
+			if from >= n {
+				// backward edge from consumer to supplier
+				amount = amount.min(flow[to][from - n]);
+			}
+		}
+
+		for edge in path.windows(2) {
+			let (to, from) = (edge[0], edge[1]);
+			if from < n {
+				flow[from][to - n] += amount;
+				total_cost += amount * cost[from][to - n];
+			} else {
+				flow[to][from - n] -= amount;
+				total_cost -= amount * cost[to][from - n];
+			}
+		}
+		supply[source] -= amount;
+		demand[target - n] -= amount;
+
+		if amount <= EPSILON {
+			break;
+		}
+	}
+
+	total_cost
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	type Lab = (f32, f32, f32);
+
+	// Sharma, Wu and Dalal: The CIEDE2000 Color-Difference Formula, table 1
+	const SHARMA: [(Lab, Lab, f32); 34] = [
+		(
+			(50.0000, 2.6772, -79.7751),
+			(50.0000, 0.0000, -82.7485),
+			2.0425,
+		),
+		(
+			(50.0000, 3.1571, -77.2803),
+			(50.0000, 0.0000, -82.7485),
+			2.8615,
+		),
+		(
+			(50.0000, 2.8361, -74.0200),
+			(50.0000, 0.0000, -82.7485),
+			3.4412,
+		),
+		(
+			(50.0000, -1.3802, -84.2814),
+			(50.0000, 0.0000, -82.7485),
+			1.0000,
+		),
+		(
+			(50.0000, -1.1848, -84.8006),
+			(50.0000, 0.0000, -82.7485),
+			1.0000,
+		),
+		(
+			(50.0000, -0.9009, -85.5211),
+			(50.0000, 0.0000, -82.7485),
+			1.0000,
+		),
+		(
+			(50.0000, 0.0000, 0.0000),
+			(50.0000, -1.0000, 2.0000),
+			2.3669,
+		),
+		(
+			(50.0000, -1.0000, 2.0000),
+			(50.0000, 0.0000, 0.0000),
+			2.3669,
+		),
+		(
+			(50.0000, 2.4900, -0.0010),
+			(50.0000, -2.4900, 0.0009),
+			7.1792,
+		),
+		(
+			(50.0000, 2.4900, -0.0010),
+			(50.0000, -2.4900, 0.0010),
+			7.1792,
+		),
+		(
+			(50.0000, 2.4900, -0.0010),
+			(50.0000, -2.4900, 0.0011),
+			7.2195,
+		),
+		(
+			(50.0000, 2.4900, -0.0010),
+			(50.0000, -2.4900, 0.0012),
+			7.2195,
+		),
+		(
+			(50.0000, -0.0010, 2.4900),
+			(50.0000, 0.0009, -2.4900),
+			4.8045,
+		),
+		(
+			(50.0000, -0.0010, 2.4900),
+			(50.0000, 0.0010, -2.4900),
+			4.8045,
+		),
+		(
+			(50.0000, -0.0010, 2.4900),
+			(50.0000, 0.0011, -2.4900),
+			4.7461,
+		),
+		(
+			(50.0000, 2.5000, 0.0000),
+			(50.0000, 0.0000, -2.5000),
+			4.3065,
+		),
+		(
+			(50.0000, 2.5000, 0.0000),
+			(73.0000, 25.0000, -18.0000),
+			27.1492,
+		),
+		(
+			(50.0000, 2.5000, 0.0000),
+			(61.0000, -5.0000, 29.0000),
+			22.8977,
+		),
+		(
+			(50.0000, 2.5000, 0.0000),
+			(56.0000, -27.0000, -3.0000),
+			31.9030,
+		),
+		(
+			(50.0000, 2.5000, 0.0000),
+			(58.0000, 24.0000, 15.0000),
+			19.4535,
+		),
+		((50.0000, 2.5000, 0.0000), (50.0000, 3.1736, 0.5854), 1.0000),
+		((50.0000, 2.5000, 0.0000), (50.0000, 3.2972, 0.0000), 1.0000),
+		((50.0000, 2.5000, 0.0000), (50.0000, 1.8634, 0.5757), 1.0000),
+		((50.0000, 2.5000, 0.0000), (50.0000, 3.2592, 0.3350), 1.0000),
+		(
+			(60.2574, -34.0099, 36.2677),
+			(60.4626, -34.1751, 39.4387),
+			1.2644,
+		),
+		(
+			(63.0109, -31.0961, -5.8663),
+			(62.8187, -29.7946, -4.0864),
+			1.2630,
+		),
+		(
+			(61.2901, 3.7196, -5.3901),
+			(61.4292, 2.2480, -4.9620),
+			1.8731,
+		),
+		(
+			(35.0831, -44.1164, 3.7933),
+			(35.0232, -40.0716, 1.5901),
+			1.8645,
+		),
+		(
+			(22.7233, 20.0904, -46.6940),
+			(23.0331, 14.9730, -42.5619),
+			2.0373,
+		),
+		(
+			(36.4612, 47.8580, 18.3852),
+			(36.2715, 50.5065, 21.2231),
+			1.4146,
+		),
+		(
+			(90.8027, -2.0831, 1.4410),
+			(91.1528, -1.6435, 0.0447),
+			1.4441,
+		),
+		(
+			(90.9257, -0.5406, -0.9208),
+			(88.6381, -0.8985, -0.7239),
+			1.5381,
+		),
+		(
+			(6.7747, -0.2908, -2.4247),
+			(5.8714, -0.0985, -2.2286),
+			0.6377,
+		),
+		(
+			(2.0776, 0.0795, -1.1350),
+			(0.9033, -0.0636, -0.5514),
+			0.9082,
+		),
+	];
+
+	#[test]
+	fn ciede2000_matches_reference_data() {
+		for (i, (lab1, lab2, expected)) in SHARMA.iter().enumerate() {
+			for (c1, c2) in [(lab1, lab2), (lab2, lab1)] {
+				let d = ciede2000(*c1, *c2);
+				assert!(
+					(d - expected).abs() < 1e-3,
+					"pair {}: {} instead of {}",
+					i + 1,
+					d,
+					expected
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn palette_emd_of_empty_palette_is_infinite() {
+		let palette = [LabColor {
+			l: 50.0,
+			a: 0.0,
+			b: 0.0,
+			weight: 1.0,
+		}];
+		assert_eq!(palette_emd(&palette, &[]), f32::INFINITY);
+		assert_eq!(palette_emd(&[], &palette), f32::INFINITY);
+		assert_eq!(palette_emd(&palette, &palette), 0.0);
+	}
+}
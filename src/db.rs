@@ -4,7 +4,13 @@ use axum::Extension;
 use uuid::Uuid;
 
 use crate::{
+	capture::{CaptureInfo, GpsPosition},
+	color::LabColor,
+	decode::Orientation,
 	err::{Error, Result},
+	phash::PerceptualHashes,
+	quality::QualityMetrics,
+	video::VideoMetadata,
 	IMAGES_PATH,
 };
 
@@ -16,7 +22,7 @@ pub async fn init(db_url: &str) -> Db {
 		.min_connections(4)
 		.max_connections(16)
 		.test_before_acquire(true)
-		.connect_lazy(&db_url)
+		.connect_lazy(db_url)
 		.unwrap();
 
 	sqlx::migrate!().run(&pool).await.unwrap();
@@ -27,15 +33,18 @@ pub async fn init(db_url: &str) -> Db {
 #[derive(serde::Serialize)]
 pub struct NewCollection {
 	pub name: String,
+	pub thumbnail_settings: ThumbnailSettings,
 }
 
 impl NewCollection {
 	pub async fn insert_one(self, db: &Db) -> sqlx::Result<Collection> {
 		let id = Uuid::new_v4();
+		let thumbnail_settings = sqlx::types::Json(self.thumbnail_settings);
 
-		sqlx::query("INSERT INTO collections VALUES ($1, $2)")
+		sqlx::query("INSERT INTO collections (id, name, thumbnail_settings) VALUES ($1, $2, $3)")
 			.bind(id)
 			.bind(&self.name)
+			.bind(&thumbnail_settings)
 			.execute(db)
 			.await?;
 
@@ -43,15 +52,131 @@ impl NewCollection {
 			id,
 			name: self.name,
 			finalized: false,
+			atlas_version: None,
+			atlas_stale: true,
+			thumbnail_settings,
+			version: 0,
 		})
 	}
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailFormat {
+	Jpeg,
+	Png,
+}
+
+impl ThumbnailFormat {
+	pub fn image_format(&self) -> image::ImageFormat {
+		match self {
+			Self::Jpeg => image::ImageFormat::Jpeg,
+			Self::Png => image::ImageFormat::Png,
+		}
+	}
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeAlgorithm {
+	Nearest,
+	Bilinear,
+	CatmullRom,
+	Mitchell,
+	Lanczos3,
+}
+
+impl ResizeAlgorithm {
+	pub fn resize_alg(&self) -> fast_image_resize::ResizeAlg {
+		use fast_image_resize::{FilterType, ResizeAlg};
+
+		match self {
+			Self::Nearest => ResizeAlg::Nearest,
+			Self::Bilinear => ResizeAlg::Convolution(FilterType::Bilinear),
+			Self::CatmullRom => ResizeAlg::Convolution(FilterType::CatmullRom),
+			Self::Mitchell => ResizeAlg::Convolution(FilterType::Mitchell),
+			Self::Lanczos3 => ResizeAlg::Convolution(FilterType::Lanczos3),
+		}
+	}
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlphaMode {
+	// keep transparency; such thumbnails are always PNG
+	Keep,
+	// blend transparent images onto the background colour
+	Flatten,
+}
+
+// frame of an animation or page of a multi-page document that thumbnails are made of
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameSelection {
+	First,
+	Middle,
+	Last,
+	// clamped to the last frame
+	Index(u32),
+}
+
+impl FrameSelection {
+	pub fn index(&self, count: u32) -> u32 {
+		let last = count.saturating_sub(1);
+		match self {
+			Self::First => 0,
+			Self::Middle => last / 2,
+			Self::Last => last,
+			Self::Index(n) => (*n).min(last),
+		}
+	}
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ThumbnailSettings {
+	// thumbnails fit into squares of these sizes; the smallest one is used for static atlases
+	pub sizes: Vec<u32>,
+	pub format: ThumbnailFormat,
+	// JPEG quality, 1-100
+	pub quality: u8,
+	pub resize_alg: ResizeAlgorithm,
+	pub alpha: AlphaMode,
+	pub background: (u8, u8, u8),
+	pub frame: FrameSelection,
+}
+
+impl Default for ThumbnailSettings {
+	fn default() -> Self {
+		Self {
+			sizes: vec![30, 500, 1000],
+			format: ThumbnailFormat::Jpeg,
+			quality: 75,
+			resize_alg: ResizeAlgorithm::Nearest,
+			alpha: AlphaMode::Flatten,
+			background: (255, 255, 255),
+			frame: FrameSelection::First,
+		}
+	}
+}
+
+impl ThumbnailSettings {
+	// bounds of the thumbnails used for static atlases
+	pub fn atlas_size(&self) -> (u32, u32) {
+		let size = self.sizes.iter().copied().min().unwrap_or(30);
+		(size, size)
+	}
+}
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 pub struct Collection {
 	pub id: sqlx::types::Uuid,
 	pub name: String,
 	pub finalized: bool,
+	pub atlas_version: Option<i32>,
+	pub atlas_stale: bool,
+	pub thumbnail_settings: sqlx::types::Json<ThumbnailSettings>,
+	pub version: i32,
 }
 
 impl Collection {
@@ -69,10 +194,45 @@ impl Collection {
 	}
 
 	pub async fn save(&self, db: &Db) -> sqlx::Result<()> {
-		sqlx::query("UPDATE collections SET name = $1, finalized = $2 WHERE id = $3")
-			.bind(&self.name)
-			.bind(self.finalized)
-			.bind(self.id)
+		sqlx::query(
+			"UPDATE collections SET name = $1, finalized = $2, thumbnail_settings = $3 WHERE id = $4",
+		)
+		.bind(&self.name)
+		.bind(self.finalized)
+		.bind(&self.thumbnail_settings)
+		.bind(self.id)
+		.execute(db)
+		.await?;
+
+		Ok(())
+	}
+
+	// call whenever images of a collection are added or modified
+	pub async fn mark_images_changed(db: &Db, id: Uuid) -> sqlx::Result<()> {
+		sqlx::query(
+			"UPDATE collections SET atlas_stale = TRUE, version = version + 1 WHERE id = $1",
+		)
+		.bind(id)
+		.execute(db)
+		.await?;
+
+		Ok(())
+	}
+
+	pub async fn set_atlas_stale(db: &Db, id: Uuid, stale: bool) -> sqlx::Result<()> {
+		sqlx::query("UPDATE collections SET atlas_stale = $2 WHERE id = $1")
+			.bind(id)
+			.bind(stale)
+			.execute(db)
+			.await?;
+
+		Ok(())
+	}
+
+	pub async fn set_atlas_version(db: &Db, id: Uuid, version: i32) -> sqlx::Result<()> {
+		sqlx::query("UPDATE collections SET atlas_version = $2 WHERE id = $1")
+			.bind(id)
+			.bind(version)
 			.execute(db)
 			.await?;
 
@@ -86,6 +246,18 @@ pub struct ImageMetadata {
 	pub name: Option<String>,
 	pub exif: Option<HashMap<String, String>>,
 	pub date_time: Option<chrono::NaiveDateTime>,
+	pub orientation: Option<Orientation>,
+	// only set for animations and multi-page documents
+	pub frame_count: Option<u32>,
+	pub duration_ms: Option<u64>,
+	pub video: Option<VideoMetadata>,
+	pub capture: Option<CaptureInfo>,
+	pub gps: Option<GpsPosition>,
+	pub hashes: Option<PerceptualHashes>,
+	pub quality: Option<QualityMetrics>,
+	// palette in CIELAB, sorted by weight
+	pub lab_palette: Option<Vec<LabColor>>,
+	pub color_histogram: Option<Vec<f32>>,
 }
 
 #[derive(sqlx::FromRow, serde::Serialize, Clone, Debug)]
@@ -115,9 +287,11 @@ impl Image {
 	}
 
 	pub async fn save(&self, db: &Db) -> sqlx::Result<()> {
-		sqlx::query("UPDATE images SET metadata = $2 WHERE id = $1")
+		sqlx::query("UPDATE images SET metadata = $2, width = $3, height = $4 WHERE id = $1")
 			.bind(self.id)
 			.bind(&self.metadata)
+			.bind(self.width as i32)
+			.bind(self.height as i32)
 			.execute(db)
 			.await?;
 
@@ -158,12 +332,21 @@ impl NewImage {
 	}
 }
 
-#[derive(sqlx::Type, Debug, Clone)]
+#[derive(sqlx::Type, Debug, Clone, PartialEq, Eq)]
 #[repr(i32)]
 pub enum ImageFileKind {
 	Original = 1,
 	Thumbnail = 2,
 	Partial = 3,
+	Keyframe = 4,
+}
+
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ImageFileAlpha {
+	Opaque = 0,
+	Kept = 1,
+	Flattened = 2,
 }
 
 #[derive(sqlx::FromRow, Debug, Clone)]
@@ -175,14 +358,20 @@ pub struct ImageFile {
 	pub height: u32,
 	pub extension: String,
 	pub kind: ImageFileKind,
+	pub alpha: ImageFileAlpha,
+	#[sqlx(try_from = "i32")]
+	pub frame: u32,
 }
 
 impl ImageFile {
+	// replaces the entry of the same size and kind, if one exists
 	pub async fn insert_one(self, db: &Db) -> Result<(), sqlx::Error> {
 		sqlx::query(
 			"
-			INSERT INTO image_files (image_id, width, height, extension, kind)
-			VALUES ($1, $2, $3, $4, $5)
+			INSERT INTO image_files (image_id, width, height, extension, kind, alpha, frame)
+			VALUES ($1, $2, $3, $4, $5, $6, $7)
+			ON CONFLICT (image_id, width, height, kind, frame)
+				DO UPDATE SET extension = EXCLUDED.extension, alpha = EXCLUDED.alpha
 			",
 		)
 		.bind(self.image_id)
@@ -190,6 +379,8 @@ impl ImageFile {
 		.bind(self.height as i32)
 		.bind(self.extension)
 		.bind(self.kind)
+		.bind(self.alpha)
+		.bind(self.frame as i32)
 		.execute(db)
 		.await
 		.map(|_| ())
@@ -237,7 +428,7 @@ impl ImageFile {
 		sqlx::query_as(
 			"
 			SELECT * FROM image_files
-			WHERE image_id = $1
+			WHERE image_id = $1 AND kind <> $4
 			ORDER BY (@ (width - $2)) + (@ (height - $3))
 			LIMIT 1
 			",
@@ -245,6 +436,60 @@ impl ImageFile {
 		.bind(id)
 		.bind(width as i32)
 		.bind(height as i32)
+		.bind(ImageFileKind::Keyframe)
+		.fetch_optional(db)
+		.await
+	}
+
+	pub async fn delete_one(&self, db: &Db) -> sqlx::Result<()> {
+		sqlx::query(
+			"
+			DELETE FROM image_files
+			WHERE
+				image_id = $1 AND
+				width = $2 AND
+				height = $3 AND
+				kind = $4 AND
+				frame = $5
+			",
+		)
+		.bind(self.image_id)
+		.bind(self.width as i32)
+		.bind(self.height as i32)
+		.bind(self.kind.clone())
+		.bind(self.frame as i32)
+		.execute(db)
+		.await
+		.map(|_| ())
+	}
+
+	pub async fn get_original(db: &Db, id: Uuid) -> sqlx::Result<Option<Self>> {
+		sqlx::query_as(
+			"
+			SELECT * FROM image_files
+			WHERE image_id = $1 AND kind = $2
+			LIMIT 1
+			",
+		)
+		.bind(id)
+		.bind(ImageFileKind::Original)
+		.fetch_optional(db)
+		.await
+	}
+
+	// largest stored version of keyframe n
+	pub async fn get_keyframe(db: &Db, id: Uuid, n: u32) -> sqlx::Result<Option<Self>> {
+		sqlx::query_as(
+			"
+			SELECT * FROM image_files
+			WHERE image_id = $1 AND kind = $2 AND frame = $3
+			ORDER BY width DESC, height DESC
+			LIMIT 1
+			",
+		)
+		.bind(id)
+		.bind(ImageFileKind::Keyframe)
+		.bind(n as i32)
 		.fetch_optional(db)
 		.await
 	}
@@ -253,12 +498,13 @@ impl ImageFile {
 		sqlx::query_as(
 			"
 			SELECT * FROM image_files
-			WHERE image_id = $1
+			WHERE image_id = $1 AND kind <> $2
 			ORDER BY width ASC, height ASC
 			LIMIT 1
 			",
 		)
 		.bind(id)
+		.bind(ImageFileKind::Keyframe)
 		.fetch_optional(db)
 		.await
 	}
@@ -271,10 +517,160 @@ impl ImageFile {
 		match self.kind {
 			ImageFileKind::Thumbnail => path.push(format!("{}x{}", self.width, self.height)),
 			ImageFileKind::Original => path.push("original"),
+			ImageFileKind::Keyframe => path.push(format!(
+				"keyframe-{}-{}x{}",
+				self.frame, self.width, self.height
+			)),
 			ImageFileKind::Partial => unimplemented!(),
 		}
 		path.set_extension(&self.extension);
 
-		return path;
+		path
+	}
+}
+
+#[derive(sqlx::FromRow)]
+pub struct StoredLayout {
+	pub id: Uuid,
+	pub request: sqlx::types::Json<serde_json::Value>,
+	pub collection_version: i32,
+	pub data: Vec<u8>,
+}
+
+// stored layout without its data
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct LayoutInfo {
+	pub id: Uuid,
+	pub name: Option<String>,
+	pub request: sqlx::types::Json<serde_json::Value>,
+	// images changed since the layout was computed
+	pub stale: bool,
+	pub created_at: chrono::NaiveDateTime,
+}
+
+impl StoredLayout {
+	// cached layout for the JSON text of a layout request
+	pub async fn get_by_request(
+		db: &Db,
+		collection_id: Uuid,
+		request: &str,
+	) -> sqlx::Result<Option<StoredLayout>> {
+		sqlx::query_as(
+			"SELECT * FROM layouts
+			WHERE collection_id = $1 AND request_hash = md5($2) AND NOT saved",
+		)
+		.bind(collection_id)
+		.bind(request)
+		.fetch_optional(db)
+		.await
+	}
+
+	pub async fn get_by_id(
+		db: &Db,
+		collection_id: Uuid,
+		id: Uuid,
+	) -> sqlx::Result<Option<StoredLayout>> {
+		sqlx::query_as("SELECT * FROM layouts WHERE collection_id = $1 AND id = $2")
+			.bind(collection_id)
+			.bind(id)
+			.fetch_optional(db)
+			.await
+	}
+
+	pub async fn get_all_for_collection(
+		db: &Db,
+		collection_id: Uuid,
+	) -> sqlx::Result<Vec<LayoutInfo>> {
+		sqlx::query_as(
+			"SELECT l.id, l.name, l.request, l.collection_version <> c.version AS stale, l.created_at
+			FROM layouts l JOIN collections c ON l.collection_id = c.id
+			WHERE l.collection_id = $1 AND l.saved
+			ORDER BY l.created_at DESC",
+		)
+		.bind(collection_id)
+		.fetch_all(db)
+		.await
+	}
+
+	// replaces the layout cached for the same request, keeping its id; saved layouts aren't touched
+	pub async fn insert_or_update(
+		db: &Db,
+		collection_id: Uuid,
+		request: &str,
+		collection_version: i32,
+		data: &[u8],
+	) -> sqlx::Result<Uuid> {
+		let (id,): (Uuid,) = sqlx::query_as(
+			"INSERT INTO layouts (collection_id, request, request_hash, collection_version, data)
+			VALUES ($1, $2::jsonb, md5($2), $3, $4)
+			ON CONFLICT (collection_id, request_hash) WHERE NOT saved DO UPDATE
+			SET collection_version = EXCLUDED.collection_version, data = EXCLUDED.data, created_at = now()
+			RETURNING id",
+		)
+		.bind(collection_id)
+		.bind(request)
+		.bind(collection_version)
+		.bind(data)
+		.fetch_one(db)
+		.await?;
+
+		Ok(id)
+	}
+
+	// keeps the layout as it is until it is deleted, rather than only caching it
+	pub async fn save(
+		db: &Db,
+		collection_id: Uuid,
+		id: Uuid,
+		name: Option<&str>,
+	) -> sqlx::Result<bool> {
+		let res = sqlx::query(
+			"UPDATE layouts SET name = $3, saved = true WHERE collection_id = $1 AND id = $2",
+		)
+		.bind(collection_id)
+		.bind(id)
+		.bind(name)
+		.execute(db)
+		.await?;
+
+		Ok(res.rows_affected() > 0)
+	}
+
+	// drops cached layouts that are stale or beyond the most recent ones
+	pub async fn prune_unsaved(
+		db: &Db,
+		collection_id: Uuid,
+		collection_version: i32,
+		keep: i64,
+	) -> sqlx::Result<()> {
+		sqlx::query(
+			"DELETE FROM layouts
+			WHERE collection_id = $1 AND NOT saved AND (
+				collection_version <> $2
+				OR id NOT IN (
+					SELECT id FROM layouts
+					WHERE collection_id = $1 AND NOT saved AND collection_version = $2
+					ORDER BY created_at DESC
+					LIMIT $3
+				)
+			)",
+		)
+		.bind(collection_id)
+		.bind(collection_version)
+		.bind(keep)
+		.execute(db)
+		.await?;
+
+		Ok(())
+	}
+
+	pub async fn delete(db: &Db, collection_id: Uuid, id: Uuid) -> sqlx::Result<bool> {
+		let res = sqlx::query("DELETE FROM layouts WHERE collection_id = $1 AND id = $2")
+			.bind(collection_id)
+			.bind(id)
+			.execute(db)
+			.await?;
+
+		Ok(res.rows_affected() > 0)
 	}
 }
@@ -0,0 +1,719 @@
+use std::io::Cursor;
+
+use image::codecs::{gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::io::Reader as ImageReader;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat};
+use tiff::decoder::Decoder as TiffDecoder;
+
+use crate::db::FrameSelection;
+use crate::err::{Error, Result};
+use crate::video::VideoMetadata;
+
+// transform applied to the decoded original, derived from the EXIF Orientation tag
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+	FlipHorizontal,
+	Rotate180,
+	FlipVertical,
+	Transpose,
+	Rotate90,
+	Transverse,
+	Rotate270,
+}
+
+impl Orientation {
+	// None for the identity transform or unknown values
+	pub fn from_exif(value: u32) -> Option<Self> {
+		match value {
+			2 => Some(Self::FlipHorizontal),
+			3 => Some(Self::Rotate180),
+			4 => Some(Self::FlipVertical),
+			5 => Some(Self::Transpose),
+			6 => Some(Self::Rotate90),
+			7 => Some(Self::Transverse),
+			8 => Some(Self::Rotate270),
+			_ => None,
+		}
+	}
+
+	pub fn swaps_dimensions(&self) -> bool {
+		matches!(
+			self,
+			Self::Transpose | Self::Rotate90 | Self::Transverse | Self::Rotate270
+		)
+	}
+
+	pub fn oriented_size(orientation: Option<Self>, (width, height): (u32, u32)) -> (u32, u32) {
+		match orientation {
+			Some(o) if o.swaps_dimensions() => (height, width),
+			_ => (width, height),
+		}
+	}
+
+	pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+		match self {
+			Self::FlipHorizontal => img.fliph(),
+			Self::Rotate180 => img.rotate180(),
+			Self::FlipVertical => img.flipv(),
+			Self::Transpose => img.rotate90().fliph(),
+			Self::Rotate90 => img.rotate90(),
+			Self::Transverse => img.rotate270().fliph(),
+			Self::Rotate270 => img.rotate270(),
+		}
+	}
+}
+
+pub fn read_orientation(exif: &exif::Exif) -> Option<Orientation> {
+	exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+		.and_then(|f| f.value.get_uint(0))
+		.and_then(Orientation::from_exif)
+}
+
+// camera RAW formats, recognized by extension since most of them look like TIFF
+pub const RAW_EXTENSIONS: &[&str] = &[
+	"3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw", "nef",
+	"nrw", "orf", "pef", "raf", "rw2", "sr2", "srf", "srw",
+];
+
+pub fn is_raw_extension(extension: &str) -> bool {
+	RAW_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+pub struct DecodedImage {
+	// decoded image, with orientation applied
+	pub image: DynamicImage,
+	// extension the original should be stored with
+	pub extension: String,
+	// dimensions of the original, as stored
+	pub original_width: u32,
+	pub original_height: u32,
+	pub orientation: Option<Orientation>,
+	// only read if requested
+	pub frames: Option<FrameInfo>,
+	pub video: Option<VideoMetadata>,
+}
+
+impl DecodedImage {
+	// videos know their duration even if their frames can't be counted
+	pub fn duration_ms(&self) -> Option<u64> {
+		self.frames
+			.as_ref()
+			.and_then(|f| f.duration_ms)
+			.or(self.video.as_ref().map(|v| v.duration_ms))
+	}
+}
+
+lazy_static::lazy_static! {
+	static ref SRGB_PROFILE: Box<qcms::Profile> = {
+		let mut profile = qcms::Profile::new_sRGB();
+		profile.precache_output_transform();
+		profile
+	};
+}
+
+// convert pixels from the embedded colour space to sRGB; unsupported profiles are left as-is
+fn convert_to_srgb(img: DynamicImage, icc_profile: &[u8]) -> DynamicImage {
+	let profile = match qcms::Profile::new_from_slice(icc_profile, false) {
+		Some(profile) => profile,
+		None => {
+			log::warn!("could not parse embedded ICC profile");
+			return img;
+		}
+	};
+
+	let has_alpha = img.color().has_alpha();
+	let data_type = match has_alpha {
+		true => qcms::DataType::RGBA8,
+		false => qcms::DataType::RGB8,
+	};
+
+	let transform =
+		match qcms::Transform::new(&profile, &SRGB_PROFILE, data_type, qcms::Intent::default()) {
+			Some(transform) => transform,
+			None => {
+				log::warn!("unsupported embedded ICC profile");
+				return img;
+			}
+		};
+
+	match has_alpha {
+		true => {
+			let mut buf = img.into_rgba8();
+			transform.apply(&mut buf);
+			buf.into()
+		}
+		false => {
+			let mut buf = img.into_rgb8();
+			transform.apply(&mut buf);
+			buf.into()
+		}
+	}
+}
+
+// develop a RAW file, orientation is left to the caller
+fn decode_raw_pipeline(
+	data: &[u8],
+) -> std::result::Result<(DynamicImage, Option<Orientation>), String> {
+	let mut raw = rawloader::decode(&mut Cursor::new(data)).map_err(|e| e.to_string())?;
+	let orientation = Orientation::from_exif(raw.orientation.to_u16() as u32);
+	raw.orientation = rawloader::Orientation::Normal;
+
+	let mut pipeline = imagepipe::Pipeline::new_from_source(imagepipe::ImageSource::Raw(raw))?;
+	let output = pipeline.output_8bit(None)?;
+	let buf = image::RgbImage::from_raw(output.width as u32, output.height as u32, output.data)
+		.ok_or("developed RAW buffer has the wrong size")?;
+
+	Ok((buf.into(), orientation))
+}
+
+// TIFF tag holding an embedded ICC profile
+const TIFF_ICC_PROFILE_TAG: u16 = 34675;
+
+// IFDs followed at most in a RAW file, guards against cycles
+const MAX_IFDS: usize = 64;
+
+// embedded JPEGs referenced by the IFDs of TIFF data, as (offset, length) into it
+// RW2 and ORF use their own magic number, but are laid out like TIFF otherwise
+fn tiff_previews(tiff: &[u8]) -> Vec<(usize, usize)> {
+	let little_endian = match tiff.get(0..2) {
+		Some(b"II") => true,
+		Some(b"MM") => false,
+		_ => return vec![],
+	};
+	let u16_at = |offset: usize| {
+		let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+		Some(match little_endian {
+			true => u16::from_le_bytes(bytes),
+			false => u16::from_be_bytes(bytes),
+		})
+	};
+	let u32_at = |offset: usize| {
+		let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+		Some(match little_endian {
+			true => u32::from_le_bytes(bytes),
+			false => u32::from_be_bytes(bytes),
+		})
+	};
+	// SHORT, LONG and IFD values; stored in the entry itself if they fit
+	let values = |entry: usize| -> Vec<u32> {
+		let (Some(kind), Some(count)) = (u16_at(entry + 2), u32_at(entry + 4)) else {
+			return vec![];
+		};
+		let size = match kind {
+			3 => 2,
+			4 | 13 => 4,
+			_ => return vec![],
+		};
+		let count = (count as usize).min(1024);
+		let start = match count * size <= 4 {
+			true => entry + 8,
+			false => u32_at(entry + 8).unwrap_or(u32::MAX) as usize,
+		};
+		(0..count)
+			.map_while(|i| match size {
+				2 => u16_at(start + i * 2).map(u32::from),
+				_ => u32_at(start + i * 4),
+			})
+			.collect()
+	};
+
+	let mut previews = vec![];
+	let mut visited = vec![];
+	let mut queue = u32_at(4).map(|ifd| vec![ifd as usize]).unwrap_or_default();
+	while let Some(ifd) = queue.pop() {
+		if ifd == 0 || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+			continue;
+		}
+		visited.push(ifd);
+
+		let Some(count) = u16_at(ifd) else {
+			continue;
+		};
+		let mut tags = std::collections::HashMap::new();
+		for i in 0..count as usize {
+			let entry = ifd + 2 + i * 12;
+			if let Some(tag) = u16_at(entry) {
+				tags.insert(tag, values(entry));
+			}
+		}
+		let first = |tag: u16| tags.get(&tag).and_then(|v| v.first()).map(|v| *v as usize);
+
+		// JPEGInterchangeFormat and JPEGInterchangeFormatLength
+		if let (Some(offset), Some(length)) = (first(0x0201), first(0x0202)) {
+			previews.push((offset, length));
+		}
+		// a single strip of JPEG compressed data
+		if matches!(first(0x0103), Some(6 | 7)) {
+			if let (Some([offset]), Some([length])) = (
+				tags.get(&0x0111).map(Vec::as_slice),
+				tags.get(&0x0117).map(Vec::as_slice),
+			) {
+				previews.push((*offset as usize, *length as usize));
+			}
+		}
+
+		// SubIFDs, then the next IFD in the chain
+		queue.extend(tags.get(&0x014A).into_iter().flatten().map(|v| *v as usize));
+		queue.extend(u32_at(ifd + 2 + count as usize * 12).map(|next| next as usize));
+	}
+
+	previews
+}
+
+// embedded JPEGs of a RAW file, as (offset, length) into it
+fn raw_previews(data: &[u8]) -> Vec<(usize, usize)> {
+	let u32_be = |offset: usize| {
+		data.get(offset..offset + 4)
+			.map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+	};
+
+	// Fujifilm: the header points at the preview
+	if data.starts_with(b"FUJIFILMCCD-RAW") {
+		return match (u32_be(84), u32_be(88)) {
+			(Some(offset), Some(length)) => vec![(offset, length)],
+			_ => vec![],
+		};
+	}
+
+	// Minolta: TIFF data is stored in the TTW block of the header
+	if data.starts_with(b"\0MRM") {
+		let end = u32_be(4).map_or(0, |len| 8 + len).min(data.len());
+		let mut block = 8;
+		while let Some(len) = u32_be(block + 4) {
+			let start = block + 8;
+			if start >= end {
+				break;
+			}
+			if &data[block..block + 4] == b"\0TTW" {
+				return tiff_previews(&data[start..end])
+					.into_iter()
+					.map(|(offset, length)| (start + offset, length))
+					.collect();
+			}
+			block = start + len;
+		}
+		return vec![];
+	}
+
+	tiff_previews(data)
+}
+
+// find the largest JPEG preview embedded in a RAW file
+fn decode_raw_preview(data: &[u8]) -> Option<DynamicImage> {
+	let (_, preview) = raw_previews(data)
+		.into_iter()
+		.filter_map(|(offset, length)| {
+			let preview = data.get(offset..offset.checked_add(length)?)?;
+			if !preview.starts_with(&[0xFF, 0xD8]) {
+				return None;
+			}
+
+			// only read the header here, the pixel data is decoded once for the best candidate
+			let (width, height) = JpegDecoder::new(Cursor::new(preview)).ok()?.dimensions();
+			Some((width as u64 * height as u64, preview))
+		})
+		.max_by_key(|(pixels, _)| *pixels)?;
+
+	let decoder = JpegDecoder::new(Cursor::new(preview)).ok()?;
+	DynamicImage::from_decoder(decoder).ok()
+}
+
+fn decode_raw(data: &[u8]) -> Result<(DynamicImage, Option<Orientation>)> {
+	match decode_raw_pipeline(data) {
+		Ok(res) => return Ok(res),
+		Err(e) => log::warn!("could not develop RAW file, falling back to preview: {}", e),
+	}
+
+	let image = decode_raw_preview(data).ok_or_else(unsupported)?;
+	Ok((image, read_exif_orientation(data)))
+}
+
+// images without EXIF data are not an error
+fn read_exif_orientation(data: &[u8]) -> Option<Orientation> {
+	exif::Reader::new()
+		.read_from_container(&mut Cursor::new(data))
+		.ok()
+		.and_then(|exif| read_orientation(&exif))
+}
+
+fn unsupported() -> Error {
+	image::ImageError::Unsupported(image::error::ImageFormatHint::Unknown.into()).into()
+}
+
+// how the contents of an original have to be decoded
+enum OriginalFormat {
+	Raw(String),
+	Image(ImageFormat),
+}
+
+impl OriginalFormat {
+	// extension is used to recognize RAW files, which can't be told apart by their contents
+	fn new(data: &[u8], extension: Option<&str>) -> Result<Self> {
+		match extension {
+			Some(extension) if is_raw_extension(extension) => {
+				Ok(Self::Raw(extension.to_lowercase()))
+			}
+			_ => {
+				let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+				reader.format().map(Self::Image).ok_or_else(unsupported)
+			}
+		}
+	}
+
+	fn extension(&self) -> String {
+		match self {
+			Self::Raw(extension) => extension.clone(),
+			Self::Image(format) => format.extensions_str()[0].to_owned(),
+		}
+	}
+}
+
+// frames of an animation or pages of a multi-page document
+pub struct FrameInfo {
+	pub count: u32,
+	// length of a single loop, animations only
+	pub duration_ms: Option<u64>,
+}
+
+// None for still images and formats without animation support
+fn animation_frames(data: &[u8], format: ImageFormat) -> Result<Option<image::Frames<'_>>> {
+	let frames = match format {
+		ImageFormat::Gif => Some(GifDecoder::new(Cursor::new(data))?.into_frames()),
+		ImageFormat::WebP => {
+			let decoder = WebPDecoder::new(Cursor::new(data))?;
+			match decoder.has_animation() {
+				true => Some(decoder.into_frames()),
+				false => None,
+			}
+		}
+		ImageFormat::Png => {
+			let decoder = PngDecoder::new(Cursor::new(data))?;
+			match decoder.is_apng() {
+				true => Some(decoder.apng().into_frames()),
+				false => None,
+			}
+		}
+		_ => None,
+	};
+
+	Ok(frames)
+}
+
+// directory indices of the pages of a TIFF, skipping reduced-resolution versions
+fn tiff_pages(decoder: &mut TiffDecoder<Cursor<&[u8]>>) -> Result<Vec<usize>> {
+	let mut pages = vec![];
+	let mut index = 0;
+	loop {
+		let subfile_type = decoder
+			.find_tag_unsigned::<u32>(tiff::tags::Tag::NewSubfileType)?
+			.unwrap_or(0);
+		if subfile_type & 1 == 0 {
+			pages.push(index);
+		}
+
+		if !decoder.more_images() {
+			break;
+		}
+		decoder.next_image()?;
+		index += 1;
+	}
+
+	Ok(pages)
+}
+
+// decode the current page of a TIFF
+fn tiff_page_image(decoder: &mut TiffDecoder<Cursor<&[u8]>>) -> Result<DynamicImage> {
+	use tiff::decoder::DecodingResult;
+	use tiff::ColorType;
+
+	let (width, height) = decoder.dimensions()?;
+	let image = match (decoder.colortype()?, decoder.read_image()?) {
+		(ColorType::Gray(8), DecodingResult::U8(buf)) => {
+			image::GrayImage::from_raw(width, height, buf).map(DynamicImage::from)
+		}
+		(ColorType::GrayA(8), DecodingResult::U8(buf)) => {
+			image::GrayAlphaImage::from_raw(width, height, buf).map(DynamicImage::from)
+		}
+		(ColorType::RGB(8), DecodingResult::U8(buf)) => {
+			image::RgbImage::from_raw(width, height, buf).map(DynamicImage::from)
+		}
+		(ColorType::RGBA(8), DecodingResult::U8(buf)) => {
+			image::RgbaImage::from_raw(width, height, buf).map(DynamicImage::from)
+		}
+		(ColorType::Gray(16), DecodingResult::U16(buf)) => {
+			image::ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLuma16)
+		}
+		(ColorType::GrayA(16), DecodingResult::U16(buf)) => {
+			image::ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLumaA16)
+		}
+		(ColorType::RGB(16), DecodingResult::U16(buf)) => {
+			image::ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb16)
+		}
+		(ColorType::RGBA(16), DecodingResult::U16(buf)) => {
+			image::ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba16)
+		}
+		_ => None,
+	};
+
+	image.ok_or_else(unsupported)
+}
+
+// frames of a GIF and the length of a loop, read from the block structure without decoding pixels
+fn gif_frames(data: &[u8]) -> Option<FrameInfo> {
+	if !data.starts_with(b"GIF") {
+		return None;
+	}
+
+	let color_table = |flags: u8| match flags & 0x80 != 0 {
+		true => 3 << ((flags & 0x07) + 1),
+		false => 0,
+	};
+	let skip_sub_blocks = |mut pos: usize| -> Option<usize> {
+		loop {
+			let len = *data.get(pos)? as usize;
+			pos += 1 + len;
+			if len == 0 {
+				return Some(pos);
+			}
+		}
+	};
+
+	let mut info = FrameInfo {
+		count: 0,
+		duration_ms: Some(0),
+	};
+	let mut delay = 0;
+	let mut pos = 13 + color_table(*data.get(10)?);
+	// a truncated file ends the animation where it breaks off
+	while let Some(block) = data.get(pos) {
+		match block {
+			// extension; the graphic control extension holds the delay of the next frame
+			0x21 => {
+				if data.get(pos + 1) == Some(&0xF9) {
+					let bytes = data.get(pos + 4..pos + 6)?;
+					delay = u16::from_le_bytes([bytes[0], bytes[1]]) as u64;
+				}
+				pos = skip_sub_blocks(pos + 2)?;
+			}
+			// image descriptor, followed by the LZW code size and the image data
+			0x2C => {
+				info.count += 1;
+				info.duration_ms = info.duration_ms.map(|d| d + delay * 10);
+				delay = 0;
+				pos += 10 + color_table(*data.get(pos + 9)?) + 1;
+				pos = skip_sub_blocks(pos)?;
+			}
+			_ => break,
+		}
+	}
+
+	Some(info)
+}
+
+// frames of an APNG and the length of a loop, from its acTL and fcTL chunks
+fn apng_frames(data: &[u8]) -> Option<FrameInfo> {
+	let u32_at = |pos: usize| {
+		data.get(pos..pos + 4)
+			.map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+	};
+	let u16_at = |pos: usize| {
+		data.get(pos..pos + 2)
+			.map(|b| u16::from_be_bytes(b.try_into().unwrap()))
+	};
+
+	let mut count = None;
+	let mut duration_ms = 0;
+	let mut pos = 8;
+	while let (Some(len), Some(kind)) = (u32_at(pos), data.get(pos + 4..pos + 8)) {
+		let chunk = pos + 8;
+		match kind {
+			b"acTL" => count = u32_at(chunk),
+			b"fcTL" => {
+				let numer = u16_at(chunk + 20)? as u64;
+				// a denominator of 0 means hundredths of a second
+				let denom = match u16_at(chunk + 22)? {
+					0 => 100,
+					denom => denom as u64,
+				};
+				duration_ms += numer * 1000 / denom;
+			}
+			b"IEND" => break,
+			_ => {}
+		}
+		pos = chunk + len as usize + 4;
+	}
+
+	Some(FrameInfo {
+		count: count?,
+		duration_ms: Some(duration_ms),
+	})
+}
+
+// RIFF chunks of a WebP, as (fourcc, payload)
+fn webp_chunks(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+	let mut pos = 12;
+	std::iter::from_fn(move || {
+		let kind = data.get(pos..pos + 4)?;
+		let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().unwrap()) as usize;
+		let payload = data.get(pos + 8..(pos + 8 + len).min(data.len()))?;
+		// chunks are padded to an even size
+		pos += 8 + len + (len & 1);
+		Some((kind, payload))
+	})
+}
+
+// frames of an animated WebP and the length of a loop, from its ANMF chunks
+fn webp_frames(data: &[u8]) -> Option<FrameInfo> {
+	let mut info = FrameInfo {
+		count: 0,
+		duration_ms: Some(0),
+	};
+	for (kind, payload) in webp_chunks(data) {
+		if kind == b"ANMF" {
+			let duration = payload.get(12..15)?;
+			info.count += 1;
+			info.duration_ms = info
+				.duration_ms
+				.map(|d| d + u32::from_le_bytes([duration[0], duration[1], duration[2], 0]) as u64);
+		}
+	}
+
+	(info.count > 0).then_some(info)
+}
+
+// counted from the container, without decoding any pixels
+fn read_frame_info(data: &[u8], format: &OriginalFormat) -> Result<Option<FrameInfo>> {
+	let info = match format {
+		OriginalFormat::Raw(_) => None,
+		OriginalFormat::Image(ImageFormat::Gif) => gif_frames(data),
+		OriginalFormat::Image(ImageFormat::Png) => apng_frames(data),
+		OriginalFormat::Image(ImageFormat::WebP) => webp_frames(data),
+		// only the directories are read
+		OriginalFormat::Image(ImageFormat::Tiff) => {
+			let mut decoder = TiffDecoder::new(Cursor::new(data))?;
+			Some(FrameInfo {
+				count: tiff_pages(&mut decoder)?.len() as u32,
+				duration_ms: None,
+			})
+		}
+		OriginalFormat::Image(_) => None,
+	};
+
+	// still images look the same as single-frame animations
+	Ok(info.filter(|info| info.count > 1))
+}
+
+// embedded ICC profile of an original, for formats that support it
+// animations have one profile for all frames, every TIFF directory may have a profile of its own
+fn icc_profile(data: &[u8], format: ImageFormat, tiff_directory: usize) -> Option<Vec<u8>> {
+	match format {
+		ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+		ImageFormat::Png => PngDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+		ImageFormat::WebP => webp_chunks(data)
+			.find(|(kind, _)| *kind == b"ICCP")
+			.map(|(_, payload)| payload.to_vec()),
+		ImageFormat::Tiff => {
+			let mut decoder = TiffDecoder::new(Cursor::new(data)).ok()?;
+			decoder.seek_to_image(tiff_directory).ok()?;
+			decoder
+				.get_tag_u8_vec(tiff::tags::Tag::Unknown(TIFF_ICC_PROFILE_TAG))
+				.ok()
+		}
+		_ => None,
+	}
+}
+
+// decode frame n without applying orientation, None if there is no such frame
+fn decode_frame(
+	data: &[u8],
+	format: &OriginalFormat,
+	n: u32,
+) -> Result<Option<(DynamicImage, Option<Orientation>)>> {
+	let format = match format {
+		OriginalFormat::Raw(_) if n == 0 => return decode_raw(data).map(Some),
+		OriginalFormat::Raw(_) => return Ok(None),
+		OriginalFormat::Image(format) => *format,
+	};
+
+	let (image, tiff_directory) = if n == 0 {
+		let reader = ImageReader::with_format(Cursor::new(data), format);
+		(reader.decode()?, 0)
+	} else if let Some(mut frames) = animation_frames(data, format)? {
+		match frames.nth(n as usize) {
+			Some(frame) => (frame?.into_buffer().into(), 0),
+			None => return Ok(None),
+		}
+	} else if format == ImageFormat::Tiff {
+		let mut decoder = TiffDecoder::new(Cursor::new(data))?;
+		let pages = tiff_pages(&mut decoder)?;
+		match pages.get(n as usize) {
+			Some(&index) => {
+				decoder.seek_to_image(index)?;
+				(tiff_page_image(&mut decoder)?, index)
+			}
+			None => return Ok(None),
+		}
+	} else {
+		return Ok(None);
+	};
+
+	// derivatives are always sRGB
+	let image = match icc_profile(data, format, tiff_directory) {
+		Some(icc_profile) => convert_to_srgb(image, &icc_profile),
+		None => image,
+	};
+
+	Ok(Some((image, read_exif_orientation(data))))
+}
+
+fn orient(image: DynamicImage, orientation: Option<Orientation>) -> DynamicImage {
+	match orientation {
+		Some(orientation) => orientation.apply(image),
+		None => image,
+	}
+}
+
+// decode the contents of an original image file into a form thumbnails can be made of
+// for animations and multi-page documents, the selected frame is decoded
+// frames are only counted if requested, or needed to find the selected one
+pub fn decode_original(
+	data: &[u8],
+	extension: Option<&str>,
+	frame: FrameSelection,
+	with_frames: bool,
+) -> Result<DecodedImage> {
+	let format = OriginalFormat::new(data, extension)?;
+	let frames = match (with_frames, frame) {
+		(false, FrameSelection::First) => None,
+		_ => read_frame_info(data, &format)?,
+	};
+	let n = frames.as_ref().map_or(0, |f| frame.index(f.count));
+
+	let (image, orientation) =
+		decode_frame(data, &format, n)?.ok_or_else(|| Error::NotFound(format!("frame {}", n)))?;
+
+	let original_width = image.width();
+	let original_height = image.height();
+
+	Ok(DecodedImage {
+		image: orient(image, orientation),
+		extension: format.extension(),
+		original_width,
+		original_height,
+		orientation,
+		frames,
+		video: None,
+	})
+}
+
+// decode a single frame or page of an original, None if there is no such frame
+pub fn decode_original_frame(
+	data: &[u8],
+	extension: Option<&str>,
+	n: u32,
+) -> Result<Option<DynamicImage>> {
+	let format = OriginalFormat::new(data, extension)?;
+	let frame = decode_frame(data, &format, n)?;
+	Ok(frame.map(|(image, orientation)| orient(image, orientation)))
+}
@@ -0,0 +1,215 @@
+use axum::extract::{Path, Query};
+use axum::{Extension, Json};
+use itertools::Itertools;
+use uuid::Uuid;
+
+use crate::db::{Collection, DbExtension, Image};
+use crate::err::{Error, Result};
+use crate::phash::{hamming, HashKind};
+
+// hashes at most this many bits apart are considered near-duplicates by default
+const DEFAULT_THRESHOLD: u32 = 8;
+
+// metric tree over Hamming distances; children are keyed by their distance to the parent
+struct BkTree {
+	nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+	hash: u64,
+	index: usize,
+	children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+	fn new() -> Self {
+		Self { nodes: vec![] }
+	}
+
+	fn insert(&mut self, hash: u64, index: usize) {
+		let new_node = self.nodes.len();
+		self.nodes.push(BkNode {
+			hash,
+			index,
+			children: vec![],
+		});
+		if new_node == 0 {
+			return;
+		}
+
+		let mut node = 0;
+		loop {
+			let d = hamming(self.nodes[node].hash, hash);
+			match self.nodes[node].children.iter().find(|(cd, _)| *cd == d) {
+				Some(&(_, child)) => node = child,
+				None => {
+					self.nodes[node].children.push((d, new_node));
+					return;
+				}
+			}
+		}
+	}
+
+	// indices of all hashes within the threshold
+	fn find(&self, hash: u64, threshold: u32, res: &mut Vec<usize>) {
+		if self.nodes.is_empty() {
+			return;
+		}
+
+		let mut stack = vec![0];
+		while let Some(node) = stack.pop() {
+			let node = &self.nodes[node];
+			let d = hamming(node.hash, hash);
+			if d <= threshold {
+				res.push(node.index);
+			}
+
+			// by the triangle inequality, only these subtrees can hold matches
+			stack.extend(
+				node.children
+					.iter()
+					.filter(|(cd, _)| cd.abs_diff(d) <= threshold)
+					.map(|(_, child)| *child),
+			);
+		}
+	}
+}
+
+pub struct UnionFind {
+	parent: Vec<usize>,
+}
+
+impl UnionFind {
+	pub fn new(len: usize) -> Self {
+		Self {
+			parent: (0..len).collect(),
+		}
+	}
+
+	pub fn find(&mut self, mut i: usize) -> usize {
+		while self.parent[i] != i {
+			self.parent[i] = self.parent[self.parent[i]];
+			i = self.parent[i];
+		}
+		i
+	}
+
+	pub fn union(&mut self, a: usize, b: usize) {
+		let (a, b) = (self.find(a), self.find(b));
+		if a != b {
+			self.parent[b] = a;
+		}
+	}
+}
+
+// groups of images connected by hashes within the threshold; the largest groups come first
+fn group_near_duplicates(hashes: &[(Uuid, u64)], threshold: u32) -> Vec<Vec<Uuid>> {
+	let mut tree = BkTree::new();
+	let mut groups = UnionFind::new(hashes.len());
+	let mut matches = vec![];
+
+	for (i, (_, hash)) in hashes.iter().enumerate() {
+		matches.clear();
+		tree.find(*hash, threshold, &mut matches);
+		for &j in matches.iter() {
+			groups.union(j, i);
+		}
+		tree.insert(*hash, i);
+	}
+
+	(0..hashes.len())
+		.map(|i| (groups.find(i), hashes[i].0))
+		.into_group_map()
+		.into_values()
+		.filter(|group| group.len() > 1)
+		.sorted_by_key(|group| std::cmp::Reverse(group.len()))
+		.collect()
+}
+
+#[derive(serde::Deserialize)]
+pub struct NearDuplicatesQuery {
+	threshold: Option<u32>,
+	hash: Option<HashKind>,
+}
+
+pub async fn get_near_duplicates(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+	Query(query): Query<NearDuplicatesQuery>,
+) -> Result<Json<Vec<Vec<Uuid>>>> {
+	Collection::get_by_id(&db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	// hashes are computed when the collection is finalized
+	let hash = query.hash.unwrap_or_default();
+	let hashes = Image::get_all_for_collection(&db, collection_id)
+		.await?
+		.into_iter()
+		.filter_map(|img| img.metadata.hashes.map(|h| (img.id, h.get(hash))))
+		.collect_vec();
+
+	let threshold = query.threshold.unwrap_or(DEFAULT_THRESHOLD);
+	let groups =
+		tokio::task::spawn_blocking(move || group_near_duplicates(&hashes, threshold)).await?;
+
+	Ok(Json(groups))
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::rngs::StdRng;
+	use rand::{Rng, SeedableRng};
+
+	use super::*;
+
+	#[test]
+	fn bk_tree_finds_the_same_hashes_as_a_linear_scan() {
+		let mut rng = StdRng::seed_from_u64(0);
+		// hashes near a few bases, so there are matches at small thresholds
+		let bases = (0..8).map(|_| rng.gen::<u64>()).collect_vec();
+		let hashes = (0..500)
+			.map(|i| {
+				bases[i % bases.len()] ^ (1 << rng.gen_range(0..64)) ^ (1 << rng.gen_range(0..64))
+			})
+			.collect_vec();
+
+		let mut tree = BkTree::new();
+		for (i, hash) in hashes.iter().enumerate() {
+			tree.insert(*hash, i);
+		}
+		for threshold in [0, 2, 4, 20] {
+			for query in hashes.iter().take(20) {
+				let mut found = vec![];
+				tree.find(*query, threshold, &mut found);
+				found.sort();
+				let expected = (0..hashes.len())
+					.filter(|&i| hamming(hashes[i], *query) <= threshold)
+					.collect_vec();
+				assert_eq!(found, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn groups_are_connected_through_chains() {
+		let ids = (1..=6).map(Uuid::from_u128).collect_vec();
+		let hashes = [
+			// 0b11 is 2 bits from 0, but joins through 0b1
+			(ids[0], 0),
+			(ids[1], 0b1),
+			(ids[2], 0b11),
+			(ids[3], u64::MAX),
+			(ids[4], u64::MAX ^ 0b1),
+			(ids[5], 0x00ff_00ff_00ff_00ff),
+		];
+		let groups = group_near_duplicates(&hashes, 1)
+			.into_iter()
+			.map(|group| group.into_iter().sorted().collect_vec())
+			.collect_vec();
+		assert_eq!(
+			groups,
+			vec![vec![ids[0], ids[1], ids[2]], vec![ids[3], ids[4]]]
+		);
+	}
+}
@@ -4,6 +4,7 @@ use axum::response::{IntoResponse, Response};
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
 	#[error("failed with message: '{1}', code: {0}")]
 	Custom(StatusCode, String),
@@ -17,6 +18,12 @@ pub enum Error {
 	#[error("image resize error: {0}")]
 	ImageResizeBufferError(#[from] fast_image_resize::ImageBufferError),
 
+	#[error("image alpha error: {0}")]
+	ImageResizeMulDivError(#[from] fast_image_resize::MulDivImageError),
+
+	#[error("tiff error: {0}")]
+	TiffError(#[from] tiff::TiffError),
+
 	#[error("io error: {0}")]
 	IoError(#[from] std::io::Error),
 
@@ -35,6 +42,9 @@ pub enum Error {
 	#[error("MessagePack serializer error: {0}")]
 	MessagePackSerializerError(#[from] rmp_serde::encode::Error),
 
+	#[error("MessagePack deserializer error: {0}")]
+	MessagePackDeserializerError(#[from] rmp_serde::decode::Error),
+
 	#[error("payload too large {0}")]
 	PayloadTooLarge(u64),
 
@@ -52,6 +62,15 @@ pub enum Error {
 
 	#[error("kamadak_exif error: {0}")]
 	KamadakExifError(#[from] exif::Error),
+
+	#[error("json error: {0}")]
+	JsonError(#[from] serde_json::Error),
+
+	#[error("video tool error: {0}")]
+	VideoToolError(String),
+
+	#[error("cancelled")]
+	Cancelled,
 }
 
 impl IntoResponse for Error {
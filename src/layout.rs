@@ -1,28 +1,62 @@
-use axum::extract::Path;
-use axum::http::StatusCode;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::http::{HeaderName, StatusCode};
 use axum::Json;
-use axum::{response::IntoResponse, Extension};
+use axum::{response::IntoResponse, response::Response, Extension};
 use chrono::NaiveDateTime;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::{Collection, DbExtension, Image};
+use crate::db::{Collection, Db, DbExtension, Image, LayoutInfo, StoredLayout};
 use crate::err::{Error, Result};
-use crate::layout::dist::{DateTimeDist, PaletteCosDist, PaletteDist};
 use crate::layout::sort::{CompareDist, SignedDist};
-use crate::uuid_to_string_serialize;
+use crate::{uuid_from_string_deserialize, uuid_to_string_serialize};
 
-use self::dist::{DistanceFunction, DistanceFunctionVariants};
+use self::annotations::Annotations;
+use self::calendar::CalendarOptions;
+use self::cluster::{Cluster, ClusterArrangement, ClusterOptions};
+use self::color_wheel::ColorWheelOptions;
+use self::dist::DistanceFunction;
 use self::filter::Filter;
+use self::flas::SortedGridOptions;
+use self::jobs::Progress;
+pub use self::jobs::{cancel_job, get_job_status, watch_job};
+use self::map::MapOptions;
+use self::pca::PcaOptions;
 use self::sort::{CompareFunction, CompareFunctionVariants};
+use self::tsne::TsneOptions;
+use self::umap::UmapOptions;
 
+mod annotations;
+mod calendar;
+mod cluster;
+mod color_wheel;
 mod dist;
+mod features;
 mod filter;
+mod flas;
+mod jobs;
+mod knn;
+mod lap;
+mod map;
+mod pca;
+mod snap;
 mod sort;
+#[cfg(test)]
+mod testing;
+mod tsne;
+mod umap;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct UuidString(#[serde(serialize_with = "uuid_to_string_serialize")] Uuid);
+pub struct UuidString(
+	#[serde(
+		serialize_with = "uuid_to_string_serialize",
+		deserialize_with = "uuid_from_string_deserialize"
+	)]
+	Uuid,
+);
 
 impl From<Uuid> for UuidString {
 	fn from(value: Uuid) -> Self {
@@ -128,41 +162,33 @@ fn sort_by<C: CompareFunction>(compare: C, metadata: &mut [Image], _opts: SortOp
 	metadata.sort_unstable_by(move |m1, m2| compare.compare(m1, m2))
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct TsneOptions {
-	pub dist: DistanceFunctionVariants,
+// embedding coordinates paired with their images
+fn positions(metadata: &[Image], y: &[(f32, f32)]) -> Vec<(UuidString, f32, f32)> {
+	metadata
+		.iter()
+		.zip(y)
+		.map(|(img, (x, y))| (UuidString(img.id), *x, *y))
+		.collect()
 }
 
-fn tsne<D: DistanceFunction + Send + Sync>(
-	dist: D,
-	metadata: &[Image],
-	_opts: TsneOptions,
-) -> Vec<(UuidString, f32, f32)> {
-	let mut tsne = bhtsne::tSNE::new(metadata);
-	tsne.epochs(1000)
-		.barnes_hut(0.5, move |a, b| f32::abs(dist.dist(a, b)));
-
-	let mut res = tsne
-		.embedding()
-		.chunks(2)
-		.zip(metadata)
-		.map(|(pos, image)| (UuidString(image.id), pos[0], pos[1]))
-		.collect_vec();
-
-	let (min_x, min_y, max_x, max_y) = res.iter().fold(
+// scales positions to 0..1 along both axes
+fn normalize_positions(positions: &mut [(UuidString, f32, f32)]) {
+	let (min_x, min_y, max_x, max_y) = positions.iter().fold(
 		(f32::MAX, f32::MAX, f32::MIN, f32::MIN),
 		|(min_x, min_y, max_x, max_y), (_, x, y)| {
 			(min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
 		},
 	);
 
-	for (_, x, y) in res.iter_mut() {
-		*x = (*x - min_x) / (max_x - min_x);
-		*y = (*y - min_y) / (max_y - min_y);
+	// a single point, or all on one line, ends up in the middle
+	let scale = |v: f32, min: f32, max: f32| match max > min {
+		true => (v - min) / (max - min),
+		false => 0.5,
+	};
+	for (_, x, y) in positions.iter_mut() {
+		*x = scale(*x, min_x, max_x);
+		*y = scale(*y, min_y, max_y);
 	}
-
-	res
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -181,7 +207,11 @@ pub enum TimeHistResolution {
 	Year,
 }
 
-fn time_hist(metadata: &[Image], opts: TimeHistOptions) -> Vec<Vec<Option<UuidString>>> {
+// groups of images and their labels
+fn time_hist(
+	metadata: &[Image],
+	opts: TimeHistOptions,
+) -> (Vec<Vec<Option<UuidString>>>, Vec<String>) {
 	use TimeHistResolution::*;
 	let group_by_fn = match opts.resolution {
 		Hour => |dt: NaiveDateTime| dt.format("%Y-%j %H").to_string(),
@@ -204,8 +234,13 @@ fn time_hist(metadata: &[Image], opts: TimeHistOptions) -> Vec<Vec<Option<UuidSt
 
 	groups
 		.into_iter()
-		.map(|(_, group)| group.map(|(id, _)| UuidString(id)).map(Some).collect_vec())
-		.collect_vec()
+		.map(|(label, group)| {
+			(
+				group.map(|(id, _)| UuidString(id)).map(Some).collect_vec(),
+				label,
+			)
+		})
+		.unzip()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -214,8 +249,16 @@ fn time_hist(metadata: &[Image], opts: TimeHistOptions) -> Vec<Vec<Option<UuidSt
 pub enum LayoutOptions {
 	Sort(SortOptions),
 	GridExpansion(ExpansionGridOptions),
+	SortedGrid(SortedGridOptions),
 	TimeHist(TimeHistOptions),
 	Tsne(TsneOptions),
+	TsneGrid(TsneOptions),
+	Umap(UmapOptions),
+	Pca(PcaOptions),
+	Map(MapOptions),
+	ColorWheel(ColorWheelOptions),
+	Clusters(ClusterOptions),
+	Calendar(CalendarOptions),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -243,16 +286,184 @@ pub enum Layout {
 	},
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayoutResponse {
+	#[serde(flatten)]
+	pub layout: Layout,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub clusters: Option<Vec<Cluster>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub annotations: Option<Annotations>,
+}
+
+impl From<Layout> for LayoutResponse {
+	fn from(layout: Layout) -> Self {
+		Self {
+			layout,
+			clusters: None,
+			annotations: None,
+		}
+	}
+}
+
+impl Layout {
+	// order of images in the layout; images close to each other in the layout
+	// should stay close to each other in the order as well
+	pub fn order(&self) -> Vec<Uuid> {
+		match self {
+			Layout::Sort { data } => data.iter().map(|id| id.0).collect(),
+			Layout::Grid { data, .. } => data.iter().flatten().flatten().map(|id| id.0).collect(),
+			Layout::Pos { data } => {
+				// interleave bits of quantized coordinates; z-order curve
+				let z_order = |x: f32, y: f32| {
+					let x = (x.clamp(0.0, 1.0) * u16::MAX as f32) as u64;
+					let y = (y.clamp(0.0, 1.0) * u16::MAX as f32) as u64;
+					(0..16).fold(0u64, |acc, bit| {
+						acc | ((x >> bit) & 1) << (2 * bit) | ((y >> bit) & 1) << (2 * bit + 1)
+					})
+				};
+
+				data.iter()
+					.sorted_by_key(|(_, x, y)| z_order(*x, *y))
+					.map(|(id, _, _)| id.0)
+					.collect()
+			}
+		}
+	}
+}
+
+// unsaved layouts kept per collection
+const CACHED_LAYOUTS: i64 = 16;
+
+// id of the stored layout a response was read from, used to save it
+const LAYOUT_ID_HEADER: HeaderName = HeaderName::from_static("x-layout-id");
+
+fn layout_response(layout: &StoredLayout) -> impl IntoResponse {
+	(
+		[(LAYOUT_ID_HEADER, layout.id.to_string())],
+		layout.data.clone(),
+	)
+}
+
+#[derive(Deserialize)]
+pub struct LayoutQuery {
+	// return a job id right away instead of waiting for the layout
+	background: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct LayoutJob {
+	#[serde(serialize_with = "uuid_to_string_serialize")]
+	job_id: Uuid,
+}
+
 pub async fn get_layout(
 	Extension(db): DbExtension,
 	Path(collection_id): Path<Uuid>,
+	Query(query): Query<LayoutQuery>,
 	Json(layout): Json<LayoutRequest>,
-) -> Result<impl IntoResponse> {
-	measure_time::info_time!("calculating layout");
+) -> Result<Response> {
+	if query.background.unwrap_or(false) {
+		let request = serde_json::to_string(&layout)?;
+		let job_id = jobs::spawn(collection_id, request, |progress| async move {
+			let layout = cached_layout(&db, collection_id, layout, progress).await?;
+			Ok(layout.id)
+		});
+
+		return Ok((StatusCode::ACCEPTED, Json(LayoutJob { job_id })).into_response());
+	}
 
+	let progress = Arc::new(Progress::default());
+	let _cancel = progress.cancel_on_drop();
+	let layout = cached_layout(&db, collection_id, layout, progress).await?;
+
+	Ok(layout_response(&layout).into_response())
+}
+
+pub async fn get_saved_layouts(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+) -> Result<Json<Vec<LayoutInfo>>> {
+	Ok(Json(
+		StoredLayout::get_all_for_collection(&db, collection_id).await?,
+	))
+}
+
+// recomputed from the stored request if images changed since; a saved layout is kept as it was,
+// the recomputed one is cached under its own id, which the response header carries
+pub async fn get_stored_layout(
+	Extension(db): DbExtension,
+	Path((collection_id, layout_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse> {
+	let layout = StoredLayout::get_by_id(&db, collection_id, layout_id)
+		.await?
+		.ok_or(Error::NotFound("layout".into()))?;
 	let collection = Collection::get_by_id(&db, collection_id)
 		.await?
 		.ok_or(Error::NotFound("collection".into()))?;
+	if layout.collection_version == collection.version {
+		return Ok(layout_response(&layout));
+	}
+
+	let req: LayoutRequest = serde_json::from_value(layout.request.0)?;
+	let progress = Arc::new(Progress::default());
+	let _cancel = progress.cancel_on_drop();
+	let layout = cached_layout(&db, collection_id, req, progress).await?;
+
+	Ok(layout_response(&layout))
+}
+
+#[derive(Deserialize)]
+pub struct SaveLayoutRequest {
+	name: Option<String>,
+}
+
+// saves a computed layout, or renames a saved one
+pub async fn save_layout(
+	Extension(db): DbExtension,
+	Path((collection_id, layout_id)): Path<(Uuid, Uuid)>,
+	Json(req): Json<SaveLayoutRequest>,
+) -> Result<StatusCode> {
+	match StoredLayout::save(&db, collection_id, layout_id, req.name.as_deref()).await? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(Error::NotFound("layout".into())),
+	}
+}
+
+pub async fn delete_stored_layout(
+	Extension(db): DbExtension,
+	Path((collection_id, layout_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode> {
+	match StoredLayout::delete(&db, collection_id, layout_id).await? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(Error::NotFound("layout".into())),
+	}
+}
+
+pub async fn compute_layout(
+	db: &Db,
+	collection_id: Uuid,
+	layout: LayoutRequest,
+) -> Result<LayoutResponse> {
+	let progress = Arc::new(Progress::default());
+	let _cancel = progress.cancel_on_drop();
+	let layout = cached_layout(db, collection_id, layout, progress).await?;
+
+	Ok(rmp_serde::from_slice(&layout.data)?)
+}
+
+// layouts are stored per request and reused until images of the collection change
+// unsaved ones are only kept for the most recent requests
+async fn cached_layout(
+	db: &Db,
+	collection_id: Uuid,
+	layout: LayoutRequest,
+	progress: Arc<Progress>,
+) -> Result<StoredLayout> {
+	let collection = Collection::get_by_id(db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
 
 	if !collection.finalized {
 		return Err(Error::Custom(
@@ -261,29 +472,59 @@ pub async fn get_layout(
 		));
 	}
 
+	let request = serde_json::to_string(&layout)?;
+	if let Some(stored) = StoredLayout::get_by_request(db, collection_id, &request).await? {
+		if stored.collection_version == collection.version {
+			return Ok(stored);
+		}
+	}
+
+	let resp = run_layout(db, collection_id, layout, progress).await?;
+	let data = rmp_serde::to_vec_named(&resp)?;
+	// images changed while computing are caught by the version read before
+	let id = StoredLayout::insert_or_update(db, collection_id, &request, collection.version, &data)
+		.await?;
+	StoredLayout::prune_unsaved(db, collection_id, collection.version, CACHED_LAYOUTS).await?;
+
+	StoredLayout::get_by_id(db, collection_id, id)
+		.await?
+		.ok_or(Error::NotFound("layout".into()))
+}
+
+async fn run_layout(
+	db: &Db,
+	collection_id: Uuid,
+	layout: LayoutRequest,
+	progress: Arc<Progress>,
+) -> Result<LayoutResponse> {
+	measure_time::info_time!("calculating layout");
+
 	// Get all images
-	let mut images = Image::get_all_for_collection(&db, collection_id).await?;
+	let mut images = Image::get_all_for_collection(db, collection_id).await?;
 
 	// Perform filtering
 	if let Some(ref filter) = layout.filter {
-		images = images.into_iter().filter(|m| filter.filter(m)).collect();
+		images.retain(|m| filter.filter(m));
 
 		if let Some(limit) = filter.limit {
-			images = Vec::from(&images[..limit]);
+			images.truncate(limit);
 		}
 	}
 
-	let resp = tokio::task::spawn_blocking(move || do_layout(layout, &mut images)).await??;
-	let msgp = rmp_serde::to_vec_named(&resp)?;
-
-	Ok(msgp)
+	tokio::task::spawn_blocking(move || do_layout(layout, &mut images, &progress)).await?
 }
 
-fn do_layout(req: LayoutRequest, images: &mut [Image]) -> Result<Layout> {
-	match req.opts {
+fn do_layout(
+	req: LayoutRequest,
+	images: &mut [Image],
+	progress: &Progress,
+) -> Result<LayoutResponse> {
+	progress.check()?;
+
+	let (layout, annotations) = match req.opts {
 		LayoutOptions::GridExpansion(opts) => {
 			// Sort images in another dispatch
-			do_layout(
+			let sorted = do_layout(
 				LayoutRequest {
 					opts: LayoutOptions::Sort(SortOptions {
 						compare: opts.compare,
@@ -291,68 +532,66 @@ fn do_layout(req: LayoutRequest, images: &mut [Image]) -> Result<Layout> {
 					filter: None,
 				},
 				images,
+				progress,
 			)?;
 
 			let data = create_expansion_grid(images, opts);
 
-			Ok(Layout::Grid {
-				data,
+			(
+				Layout::Grid {
+					data,
+					invert: false,
+				},
+				sorted.annotations,
+			)
+		}
+		LayoutOptions::SortedGrid(opts) => (
+			Layout::Grid {
+				data: flas::sorted_grid(images, &opts, progress)?,
 				invert: false,
-			})
+			},
+			None,
+		),
+		LayoutOptions::TimeHist(opts) => {
+			let (data, labels) = time_hist(images, opts);
+
+			(
+				Layout::Grid { data, invert: true },
+				Some(Annotations::with_labels(labels)),
+			)
 		}
-		LayoutOptions::TimeHist(opts) => Ok(Layout::Grid {
-			data: time_hist(images, opts),
-			invert: true,
-		}),
 		LayoutOptions::Sort(opts) => {
-			match opts.compare {
-				CompareFunctionVariants::SignedDist { dist } => match dist {
-					DistanceFunctionVariants::Palette => {
-						sort_by(SignedDist { dist: PaletteDist }, images, opts)
-					}
-					DistanceFunctionVariants::PaletteCos => sort_by(
-						SignedDist {
-							dist: PaletteCosDist,
-						},
-						images,
-						opts,
-					),
-					DistanceFunctionVariants::DateTime => {
-						sort_by(SignedDist { dist: DateTimeDist }, images, opts)
+			let annotations = match opts.compare {
+				CompareFunctionVariants::SignedDist { dist } => {
+					if !dist.is_signed() {
+						return Err(Error::Custom(
+							StatusCode::BAD_REQUEST,
+							format!(
+								"{:?} distances have no sign to sort by, use comparative_dist instead",
+								dist
+							),
+						));
 					}
-				},
+					let dist = dist.get_function();
+					sort_by(SignedDist { dist: &dist }, images, opts);
+					Annotations::with_keys(images, &dist)
+				}
 				CompareFunctionVariants::ComparativeDist { compared_to, dist } => {
 					let compared_to = images
 						.iter()
 						.find(|i| i.id == compared_to)
 						.cloned()
 						.ok_or(Error::NotFound(format!("image with id {}", compared_to)))?;
-					match dist {
-						DistanceFunctionVariants::Palette => sort_by(
-							CompareDist {
-								compared_to,
-								dist: PaletteDist,
-							},
-							images,
-							opts,
-						),
-						DistanceFunctionVariants::PaletteCos => sort_by(
-							CompareDist {
-								compared_to,
-								dist: PaletteCosDist,
-							},
-							images,
-							opts,
-						),
-						DistanceFunctionVariants::DateTime => sort_by(
-							CompareDist {
-								compared_to,
-								dist: DateTimeDist,
-							},
-							images,
-							opts,
-						),
-					}
+					let compare = CompareDist {
+						compared_to,
+						dist: dist.get_function(),
+					};
+					sort_by(&compare, images, opts);
+
+					// distances to the image compared to
+					Annotations::with_values(images, |img| {
+						Some(compare.dist.dist(&compare.compared_to, img) as f64)
+					})
 				}
 			};
 
@@ -361,16 +600,196 @@ fn do_layout(req: LayoutRequest, images: &mut [Image]) -> Result<Layout> {
 				.map(|image| UuidString(image.id))
 				.collect_vec();
 
-			Ok(Layout::Sort { data })
+			(Layout::Sort { data }, annotations)
 		}
 		LayoutOptions::Tsne(opts) => {
-			let data = match opts.dist {
-				DistanceFunctionVariants::Palette => tsne(PaletteDist, images, opts),
-				DistanceFunctionVariants::PaletteCos => tsne(PaletteCosDist, images, opts),
-				DistanceFunctionVariants::DateTime => tsne(DateTimeDist, images, opts),
+			let data = tsne::tsne(opts.dist.get_function(), images, opts, progress)?;
+
+			(Layout::Pos { data }, None)
+		}
+		LayoutOptions::TsneGrid(opts) => {
+			let positions = tsne::tsne(opts.dist.get_function(), images, opts, progress)?;
+
+			(
+				Layout::Grid {
+					data: lap::assign_to_grid(&positions, progress)?,
+					invert: false,
+				},
+				None,
+			)
+		}
+		LayoutOptions::Umap(opts) => (
+			Layout::Pos {
+				data: umap::umap(opts.dist.get_function(), images, opts, progress)?,
+			},
+			None,
+		),
+		LayoutOptions::Pca(opts) => (
+			Layout::Pos {
+				data: pca::pca(opts.dist.get_function(), images, opts, progress)?,
+			},
+			None,
+		),
+		LayoutOptions::Map(opts) => {
+			let (data, annotations) = map::map(images, opts, progress)?;
+
+			(Layout::Pos { data }, Some(annotations))
+		}
+		LayoutOptions::ColorWheel(opts) => (
+			Layout::Pos {
+				data: color_wheel::color_wheel(images, opts, progress)?,
+			},
+			Some(color_wheel::annotations(opts)),
+		),
+		LayoutOptions::Clusters(opts) => {
+			let clusters = cluster::clusters(opts.dist.get_function(), images, opts, progress)?;
+			let (layout, groups) = match opts.arrangement.unwrap_or_default() {
+				ClusterArrangement::Blocks => {
+					let (data, groups) = cluster::blocks(&clusters);
+					(
+						Layout::Grid {
+							data,
+							invert: false,
+						},
+						groups,
+					)
+				}
+				ClusterArrangement::Piles => {
+					let (data, groups) = cluster::piles(&clusters);
+					(Layout::Pos { data }, groups)
+				}
 			};
 
-			Ok(Layout::Pos { data })
+			return Ok(LayoutResponse {
+				layout,
+				clusters: Some(clusters),
+				annotations: Some(Annotations {
+					groups: Some(groups),
+					..Default::default()
+				}),
+			});
+		}
+		LayoutOptions::Calendar(opts) => {
+			let (data, labels) = calendar::calendar(images, opts)?;
+
+			(
+				Layout::Grid {
+					data,
+					invert: false,
+				},
+				Some(Annotations::with_labels(labels)),
+			)
+		}
+	};
+
+	Ok(LayoutResponse {
+		layout,
+		clusters: None,
+		annotations,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// layouts are cached as MessagePack and decoded again for atlases
+	fn roundtrip(resp: &LayoutResponse) -> LayoutResponse {
+		let data = rmp_serde::to_vec_named(resp).unwrap();
+		rmp_serde::from_slice(&data).unwrap()
+	}
+
+	#[test]
+	fn layout_response_roundtrip() {
+		let (a, b) = (UuidString(Uuid::new_v4()), UuidString(Uuid::new_v4()));
+
+		let resp = roundtrip(&LayoutResponse {
+			layout: Layout::Pos {
+				data: vec![(a, 0.25, 0.5), (b, 1.0, 0.0)],
+			},
+			clusters: Some(vec![Cluster {
+				representative: a,
+				size: 2,
+				members: vec![a, b],
+				noise: false,
+			}]),
+			annotations: Some(Annotations {
+				values: Some(vec![(a, 1.5)]),
+				..Default::default()
+			}),
+		});
+		match resp.layout {
+			Layout::Pos { data } => {
+				assert_eq!(data[1].0 .0, b.0);
+				assert_eq!((data[0].1, data[0].2), (0.25, 0.5));
+			}
+			layout => panic!("unexpected layout {:?}", layout),
 		}
+		assert_eq!(resp.clusters.unwrap()[0].members[1].0, b.0);
+		assert_eq!(resp.annotations.unwrap().values.unwrap()[0].0 .0, a.0);
+
+		let resp = roundtrip(
+			&Layout::Grid {
+				data: vec![vec![Some(a), None], vec![Some(b), None]],
+				invert: true,
+			}
+			.into(),
+		);
+		match resp.layout {
+			Layout::Grid { data, invert } => {
+				assert!(invert);
+				assert_eq!(data[1][0].unwrap().0, b.0);
+				assert!(data[1][1].is_none());
+			}
+			layout => panic!("unexpected layout {:?}", layout),
+		}
+
+		let resp = roundtrip(&Layout::Sort { data: vec![b, a] }.into());
+		match resp.layout {
+			Layout::Sort { data } => assert_eq!(data[0].0, b.0),
+			layout => panic!("unexpected layout {:?}", layout),
+		}
+	}
+
+	fn hashed_images(hashes: &[u64]) -> Vec<Image> {
+		let (mut images, _) = testing::images_at(&vec![(0.0, 0.0); hashes.len()]);
+		for (img, hash) in images.iter_mut().zip(hashes) {
+			img.metadata.hashes = Some(crate::phash::PerceptualHashes {
+				ahash: *hash,
+				dhash: *hash,
+				phash: *hash,
+			});
+		}
+		images
+	}
+
+	#[test]
+	fn perceptual_hashes_sort_by_comparison_only() {
+		let mut images = hashed_images(&[u64::MAX, 0b111, 0, 0b1]);
+		let request = |compare: &str| -> LayoutRequest {
+			serde_json::from_str(&format!(
+				r#"{{"type": "grid_expansion", "compare": {}}}"#,
+				compare
+			))
+			.unwrap()
+		};
+
+		let signed = request(r#"{"type": "signed_dist", "dist": {"type": "perceptual_hash"}}"#);
+		match do_layout(signed, &mut images, &Progress::default()) {
+			Err(Error::Custom(status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+			res => panic!("unexpected result {:?}", res.map(|_| ())),
+		}
+
+		let compared_to = images[2].id;
+		let comparative = request(&format!(
+			r#"{{"type": "comparative_dist", "compared_to": "{}", "dist": {{"type": "perceptual_hash"}}}}"#,
+			compared_to
+		));
+		do_layout(comparative, &mut images, &Progress::default()).unwrap();
+		let order = images
+			.iter()
+			.map(|img| img.metadata.hashes.unwrap().phash)
+			.collect_vec();
+		assert_eq!(order, vec![0, 0b1, 0b111, u64::MAX]);
 	}
 }
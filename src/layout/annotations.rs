@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+
+use super::dist::DistanceFunction;
+use super::UuidString;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ValueRange {
+	pub min: f64,
+	pub max: f64,
+}
+
+// values at the start (0) and end (1) of an axis of a position layout
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Axis {
+	pub label: String,
+	pub start: f64,
+	pub end: f64,
+}
+
+// area covered by a group; in cells for grids, in layout coordinates for positions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupAnnotation {
+	pub label: String,
+	pub x: f32,
+	pub y: f32,
+	pub width: f32,
+	pub height: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LegendEntry {
+	pub label: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub color: Option<(u8, u8, u8)>,
+	// where the entry belongs in the layout, if anywhere
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub position: Option<(f32, f32)>,
+}
+
+// captions for the frontend, so it doesn't have to derive them from metadata
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Annotations {
+	// one label per row of a grid, or per column if it is inverted
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub labels: Option<Vec<String>>,
+	// the value images were arranged by, for images that have one
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub values: Option<Vec<(UuidString, f64)>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub range: Option<ValueRange>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub x_axis: Option<Axis>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub y_axis: Option<Axis>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub groups: Option<Vec<GroupAnnotation>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub legend: Option<Vec<LegendEntry>>,
+}
+
+impl Annotations {
+	pub fn with_labels(labels: Vec<String>) -> Self {
+		Self {
+			labels: Some(labels),
+			..Default::default()
+		}
+	}
+
+	// values in the order of the images, along with their range; None if no image has one
+	pub fn with_values<F: Fn(&Image) -> Option<f64>>(images: &[Image], value: F) -> Option<Self> {
+		let values = images
+			.iter()
+			.filter_map(|img| {
+				value(img)
+					.filter(|v| v.is_finite())
+					.map(|v| (UuidString(img.id), v))
+			})
+			.collect::<Vec<_>>();
+		if values.is_empty() {
+			return None;
+		}
+
+		let range = values.iter().fold(
+			ValueRange {
+				min: f64::MAX,
+				max: f64::MIN,
+			},
+			|range, (_, v)| ValueRange {
+				min: range.min.min(*v),
+				max: range.max.max(*v),
+			},
+		);
+
+		Some(Self {
+			values: Some(values),
+			range: Some(range),
+			..Default::default()
+		})
+	}
+
+	// the values distances are computed from, if the distance function has them
+	pub fn with_keys<D: DistanceFunction>(images: &[Image], dist: &D) -> Option<Self> {
+		Self::with_values(images, |img| dist.key(img))
+	}
+}
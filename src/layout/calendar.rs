@@ -0,0 +1,220 @@
+use axum::http::StatusCode;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::{Error, Result};
+
+use super::UuidString;
+
+// guards against bogus dates turning into millions of empty rows
+const MAX_PERIODS: usize = 100_000;
+pub const UNDATED_LABEL: &str = "undated";
+
+type Rows = Vec<Vec<Option<UuidString>>>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalendarResolution {
+	Day,
+	Week,
+	Month,
+	Year,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CalendarOptions {
+	pub resolution: CalendarResolution,
+	// group by the time at this offset from UTC, for images whose offset is known;
+	// otherwise by the local time at which they were taken
+	pub utc_offset_minutes: Option<i32>,
+}
+
+impl CalendarResolution {
+	fn start_of(&self, date: NaiveDate) -> NaiveDate {
+		match self {
+			Self::Day => date,
+			Self::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+			Self::Month => date.with_day(1).unwrap(),
+			Self::Year => date.with_ordinal(1).unwrap(),
+		}
+	}
+
+	fn next(&self, start: NaiveDate) -> Option<NaiveDate> {
+		match self {
+			Self::Day => start.succ_opt(),
+			Self::Week => start.checked_add_signed(Duration::weeks(1)),
+			Self::Month => match start.month() {
+				12 => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1),
+				month => NaiveDate::from_ymd_opt(start.year(), month + 1, 1),
+			},
+			Self::Year => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1),
+		}
+	}
+
+	fn label(&self, start: NaiveDate) -> String {
+		let format = match self {
+			Self::Day => "%Y-%m-%d",
+			Self::Week => "%G-W%V",
+			Self::Month => "%Y-%m",
+			Self::Year => "%Y",
+		};
+		start.format(format).to_string()
+	}
+}
+
+// capture time in the requested offset; the EXIF offset belongs to the original capture time,
+// so it is only applied if that is where the date came from
+fn date_time(img: &Image, utc_offset_minutes: Option<i32>) -> Option<NaiveDateTime> {
+	let date_time = img.metadata.date_time?;
+	let capture = img.metadata.capture.as_ref();
+	let offset = capture
+		.filter(|c| c.date_time_original == Some(date_time))
+		.and_then(|c| c.utc_offset_minutes);
+
+	match (utc_offset_minutes, offset) {
+		(Some(target), Some(offset)) => {
+			Some(date_time + Duration::minutes(target as i64 - offset as i64))
+		}
+		_ => Some(date_time),
+	}
+}
+
+// one row per period from the first to the last image, including empty ones, images in order of time
+// images without a date are in a last row; returns the rows and their labels
+pub fn calendar(metadata: &[Image], opts: CalendarOptions) -> Result<(Rows, Vec<String>)> {
+	let resolution = opts.resolution;
+	let (dated, undated): (Vec<_>, Vec<_>) = metadata
+		.iter()
+		.map(|img| (UuidString(img.id), date_time(img, opts.utc_offset_minutes)))
+		.partition(|(_, dt)| dt.is_some());
+
+	let dated = dated
+		.into_iter()
+		.map(|(id, dt)| (id, dt.unwrap()))
+		.sorted_by_key(|(_, dt)| *dt)
+		.collect_vec();
+
+	let mut rows = vec![];
+	let mut labels = vec![];
+	if let (Some((_, first)), Some((_, last))) = (dated.first(), dated.last()) {
+		let mut images = dated.iter().peekable();
+		let mut start = Some(resolution.start_of(first.date()));
+		while let Some(period) = start.filter(|s| *s <= last.date()) {
+			if rows.len() >= MAX_PERIODS {
+				return Err(Error::Custom(
+					StatusCode::BAD_REQUEST,
+					"too many periods, use a coarser resolution".into(),
+				));
+			}
+
+			let next = resolution.next(period);
+			let row = images
+				.peeking_take_while(|(_, dt)| next.is_none_or(|next| dt.date() < next))
+				.map(|(id, _)| Some(*id))
+				.collect_vec();
+
+			rows.push(row);
+			labels.push(resolution.label(period));
+			start = next;
+		}
+	}
+
+	if !undated.is_empty() {
+		rows.push(undated.into_iter().map(|(id, _)| Some(id)).collect());
+		labels.push(UNDATED_LABEL.into());
+	}
+
+	Ok((rows, labels))
+}
+
+#[cfg(test)]
+mod tests {
+	use uuid::Uuid;
+
+	use super::super::testing::images_at;
+	use super::*;
+	use crate::capture::CaptureInfo;
+
+	fn at(date: &str) -> NaiveDateTime {
+		NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+	}
+
+	// images taken at the given local times, in order of their ids
+	fn images(dates: &[Option<&str>]) -> Vec<Image> {
+		let (mut images, _) = images_at(&vec![(0.0, 0.0); dates.len()]);
+		for (img, date) in images.iter_mut().zip(dates) {
+			img.metadata.date_time = date.map(at);
+		}
+		images
+	}
+
+	fn opts(resolution: CalendarResolution) -> CalendarOptions {
+		CalendarOptions {
+			resolution,
+			utc_offset_minutes: None,
+		}
+	}
+
+	// ids as the index into the images
+	fn indices(rows: &Rows) -> Vec<Vec<usize>> {
+		rows.iter()
+			.map(|row| {
+				row.iter()
+					.map(|id| id.unwrap().0.as_u128() as usize - 1)
+					.collect()
+			})
+			.collect()
+	}
+
+	#[test]
+	fn empty_periods_are_kept_and_undated_images_come_last() {
+		let images = images(&[
+			Some("2023-01-15 12:00"),
+			Some("2023-01-03 08:00"),
+			None,
+			Some("2023-03-31 23:59"),
+		]);
+		let (rows, labels) = calendar(&images, opts(CalendarResolution::Month)).unwrap();
+		assert_eq!(indices(&rows), vec![vec![1, 0], vec![], vec![3], vec![2]]);
+		assert_eq!(labels, ["2023-01", "2023-02", "2023-03", UNDATED_LABEL]);
+	}
+
+	#[test]
+	fn weeks_start_on_monday() {
+		// a Sunday and the Monday after it
+		let images = images(&[Some("2023-01-02 00:00"), Some("2023-01-01 23:59")]);
+		let (rows, labels) = calendar(&images, opts(CalendarResolution::Week)).unwrap();
+		assert_eq!(indices(&rows), vec![vec![1], vec![0]]);
+		assert_eq!(labels, ["2022-W52", "2023-W01"]);
+	}
+
+	#[test]
+	fn known_offsets_are_shifted_to_the_requested_one() {
+		let mut images = images(&[Some("2023-01-01 23:30"), Some("2023-01-01 23:45")]);
+		// only the first image knows it was taken in UTC
+		images[0].metadata.capture = Some(CaptureInfo {
+			date_time_original: Some(at("2023-01-01 23:30")),
+			utc_offset_minutes: Some(0),
+			..Default::default()
+		});
+		let opts = CalendarOptions {
+			resolution: CalendarResolution::Day,
+			utc_offset_minutes: Some(60),
+		};
+		let (rows, labels) = calendar(&images, opts).unwrap();
+		assert_eq!(indices(&rows), vec![vec![1], vec![0]]);
+		assert_eq!(labels, ["2023-01-01", "2023-01-02"]);
+	}
+
+	#[test]
+	fn too_many_periods() {
+		let images = images(&[Some("1000-01-01 00:00"), Some("2023-01-01 00:00")]);
+		assert!(calendar(&images, opts(CalendarResolution::Day)).is_err());
+		let (rows, _) = calendar(&images, opts(CalendarResolution::Year)).unwrap();
+		assert_eq!(rows.len(), 1024);
+		assert_eq!(rows[0][0].map(|id| id.0), Some(Uuid::from_u128(1)));
+	}
+}
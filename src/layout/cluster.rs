@@ -0,0 +1,488 @@
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::duplicates::UnionFind;
+use crate::err::Result;
+
+use super::annotations::GroupAnnotation;
+use super::dist::{DistanceFunction, DistanceFunctionVariants};
+use super::jobs::Progress;
+use super::knn::nearest_neighbours;
+use super::UuidString;
+
+const DEFAULT_ITERATIONS: u32 = 20;
+const DEFAULT_MIN_POINTS: usize = 5;
+// larger densities would need too many nearest neighbours per image
+const MAX_MIN_POINTS: usize = 128;
+// density and linkage are computed over this many nearest neighbours at least
+const NEIGHBOURS: usize = 32;
+// medoids are chosen among this many candidates, scored against this many members
+const MEDOID_CANDIDATES: usize = 64;
+const MEDOID_SAMPLE: usize = 256;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum ClusterAlgorithm {
+	// only distances are known, so cluster centres are medoids rather than means
+	KMeans { k: usize, iterations: Option<u32> },
+	// images with fewer than min_points neighbours within eps, and not close to a dense image, are noise
+	// min_points is at most 128
+	Dbscan { eps: f32, min_points: Option<usize> },
+	// single linkage, merging the closest clusters until k are left
+	Agglomerative { k: usize },
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterArrangement {
+	// each cluster fills a square block of the grid, blocks are separated by an empty cell
+	#[default]
+	Blocks,
+	// all images of a cluster share one position
+	Piles,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ClusterOptions {
+	pub dist: DistanceFunctionVariants,
+	pub algorithm: ClusterAlgorithm,
+	pub arrangement: Option<ClusterArrangement>,
+	pub seed: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cluster {
+	// the medoid, i.e. the image with the smallest distance to the other members
+	pub representative: UuidString,
+	pub size: usize,
+	// closest to the representative first
+	pub members: Vec<UuidString>,
+	// images DBSCAN didn't assign to any cluster
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub noise: bool,
+}
+
+// index of the member with the smallest total distance to a sample of the others
+fn medoid<D: Fn(usize, usize) -> f32>(members: &[usize], dist: &D, rng: &mut StdRng) -> usize {
+	let pick = |rng: &mut StdRng, amount: usize| {
+		sample(rng, members.len(), amount.min(members.len()))
+			.into_iter()
+			.map(|i| members[i])
+			.collect_vec()
+	};
+	let candidates = pick(rng, MEDOID_CANDIDATES);
+	let others = pick(rng, MEDOID_SAMPLE);
+
+	candidates
+		.into_iter()
+		.map(|c| {
+			let cost = others
+				.iter()
+				.map(|o| dist(c, *o).min(f32::MAX))
+				.sum::<f32>();
+			(c, cost)
+		})
+		.min_by(|(_, a), (_, b)| a.total_cmp(b))
+		.unwrap()
+		.0
+}
+
+// cluster index of every image; k-means++ seeding, then alternating assignment and medoid updates
+fn k_means<D: Fn(usize, usize) -> f32>(
+	len: usize,
+	k: usize,
+	iterations: u32,
+	dist: &D,
+	rng: &mut StdRng,
+	progress: &Progress,
+) -> Result<Vec<Option<usize>>> {
+	let k = k.clamp(1, len);
+
+	let mut centres = vec![rng.gen_range(0..len)];
+	let mut closest = (0..len).map(|i| dist(i, centres[0])).collect_vec();
+	while centres.len() < k {
+		progress.check()?;
+
+		// images far away from all centres are more likely to become the next one
+		let weights = closest
+			.iter()
+			.map(|d| match d.is_finite() {
+				true => d * d,
+				false => 0.0,
+			})
+			.collect_vec();
+		let total = weights.iter().sum::<f32>();
+		let next = match total > 0.0 {
+			true => {
+				let mut target = rng.gen_range(0.0..total);
+				weights
+					.iter()
+					.position(|w| {
+						target -= w;
+						target < 0.0
+					})
+					.unwrap_or(len - 1)
+			}
+			false => rng.gen_range(0..len),
+		};
+
+		centres.push(next);
+		for (i, c) in closest.iter_mut().enumerate() {
+			*c = c.min(dist(i, next));
+		}
+	}
+
+	let mut assignment = vec![0; len];
+	for _ in 0..iterations {
+		progress.check()?;
+
+		let next = (0..len)
+			.map(|i| {
+				centres
+					.iter()
+					.map(|c| dist(i, *c))
+					.enumerate()
+					.min_by(|(_, a), (_, b)| a.total_cmp(b))
+					.unwrap()
+					.0
+			})
+			.collect_vec();
+		let changed = next != assignment;
+		assignment = next;
+
+		let members = (0..len).into_group_map_by(|i| assignment[*i]);
+		let moved = (0..k)
+			.filter_map(|c| {
+				let m = medoid(members.get(&c)?, dist, rng);
+				(m != centres[c]).then(|| centres[c] = m)
+			})
+			.count();
+
+		if !changed && moved == 0 {
+			break;
+		}
+	}
+
+	Ok(assignment.into_iter().map(Some).collect())
+}
+
+// DBSCAN over the nearest neighbours; exact as long as no image has more than
+// NEIGHBOURS neighbours within eps that matter for its density
+fn dbscan(
+	neighbours: &[Vec<(usize, f32)>],
+	eps: f32,
+	min_points: usize,
+	progress: &Progress,
+) -> Result<Vec<Option<usize>>> {
+	let within = |i: usize| neighbours[i].iter().take_while(move |(_, d)| *d <= eps);
+	// the image itself counts towards its density
+	let core = (0..neighbours.len())
+		.map(|i| within(i).count() + 1 >= min_points)
+		.collect_vec();
+
+	let mut clusters = UnionFind::new(neighbours.len());
+	for i in (0..neighbours.len()).filter(|i| core[*i]) {
+		progress.check()?;
+		for (j, _) in within(i).filter(|(j, _)| core[*j]) {
+			clusters.union(i, *j);
+		}
+	}
+
+	// border images join the cluster of the closest core image
+	let mut assignment = (0..neighbours.len())
+		.map(|i| core[i].then(|| clusters.find(i)))
+		.collect_vec();
+	for i in (0..neighbours.len()).filter(|i| !core[*i]) {
+		if let Some((j, _)) = within(i).find(|(j, _)| core[*j]) {
+			assignment[i] = Some(clusters.find(*j));
+		}
+	}
+
+	Ok(assignment)
+}
+
+// single linkage is the minimum spanning forest, cut into k trees; computed on the
+// nearest neighbour graph, so clusters that are not connected in it stay apart
+fn agglomerative(neighbours: &[Vec<(usize, f32)>], k: usize) -> Vec<Option<usize>> {
+	let edges = neighbours
+		.iter()
+		.enumerate()
+		.flat_map(|(i, n)| n.iter().map(move |(j, d)| (i, *j, *d)))
+		.sorted_by(|a, b| a.2.total_cmp(&b.2));
+
+	let mut clusters = UnionFind::new(neighbours.len());
+	let mut count = neighbours.len();
+	for (i, j, _) in edges {
+		if count <= k.max(1) {
+			break;
+		}
+		if clusters.find(i) != clusters.find(j) {
+			clusters.union(i, j);
+			count -= 1;
+		}
+	}
+
+	(0..neighbours.len())
+		.map(|i| Some(clusters.find(i)))
+		.collect()
+}
+
+pub fn clusters<D: DistanceFunction>(
+	dist: D,
+	metadata: &[Image],
+	opts: ClusterOptions,
+	progress: &Progress,
+) -> Result<Vec<Cluster>> {
+	if metadata.is_empty() {
+		return Ok(vec![]);
+	}
+
+	let mut rng = StdRng::seed_from_u64(opts.seed.unwrap_or_default());
+	let dist = |i: usize, j: usize| dist.dist(&metadata[i], &metadata[j]).abs();
+
+	let assignment = match opts.algorithm {
+		ClusterAlgorithm::KMeans { k, iterations } => k_means(
+			metadata.len(),
+			k,
+			iterations.unwrap_or(DEFAULT_ITERATIONS),
+			&dist,
+			&mut rng,
+			progress,
+		)?,
+		ClusterAlgorithm::Dbscan { eps, min_points } => {
+			let min_points = min_points.unwrap_or(DEFAULT_MIN_POINTS).min(MAX_MIN_POINTS);
+			let neighbours = nearest_neighbours(
+				metadata.len(),
+				NEIGHBOURS.max(min_points),
+				dist,
+				&mut rng,
+				progress,
+			)?;
+			dbscan(&neighbours, eps, min_points, progress)?
+		}
+		ClusterAlgorithm::Agglomerative { k } => {
+			let neighbours =
+				nearest_neighbours(metadata.len(), NEIGHBOURS, dist, &mut rng, progress)?;
+			agglomerative(&neighbours, k)
+		}
+	};
+
+	let groups = (0..metadata.len()).into_group_map_by(|i| assignment[*i]);
+	// in a fixed order, so medoids are sampled the same way every time
+	let clusters = groups
+		.into_iter()
+		.sorted_by_key(|(cluster, _)| *cluster)
+		.map(|(cluster, members)| {
+			progress.check()?;
+
+			let representative = medoid(&members, &dist, &mut rng);
+			let members = members
+				.into_iter()
+				.map(|i| (i, dist(representative, i)))
+				.sorted_by(|(_, a), (_, b)| a.total_cmp(b))
+				.map(|(i, _)| UuidString(metadata[i].id))
+				.collect_vec();
+
+			Ok(Cluster {
+				representative: UuidString(metadata[representative].id),
+				size: members.len(),
+				members,
+				noise: cluster.is_none(),
+			})
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	// largest clusters first, noise last
+	Ok(clusters
+		.into_iter()
+		.sorted_by_key(|c| (c.noise, std::cmp::Reverse(c.size)))
+		.collect())
+}
+
+fn label(i: usize, cluster: &Cluster) -> String {
+	match cluster.noise {
+		true => "noise".into(),
+		false => format!("cluster {}", i + 1),
+	}
+}
+
+// square blocks, packed into rows of roughly the same width as the whole grid is high
+pub fn blocks(clusters: &[Cluster]) -> (Vec<Vec<Option<UuidString>>>, Vec<GroupAnnotation>) {
+	let sides = clusters
+		.iter()
+		.map(|c| (c.size as f32).sqrt().ceil() as usize)
+		.collect_vec();
+	let area = sides.iter().map(|s| (s + 1) * (s + 1)).sum::<usize>();
+	// the last block in a row needs no gap after it
+	let width =
+		((area as f32).sqrt().ceil() as usize + 1).max(sides.iter().copied().max().unwrap_or(0));
+
+	let mut grid: Vec<Vec<Option<UuidString>>> = vec![];
+	let mut groups = vec![];
+	let (mut x, mut y, mut shelf_height, mut used_width) = (0, 0, 0, 0);
+	for (i, (cluster, side)) in clusters.iter().zip(sides).enumerate() {
+		if x > 0 && x + side > width {
+			x = 0;
+			y += shelf_height + 1;
+			shelf_height = 0;
+		}
+
+		grid.resize(grid.len().max(y + side), vec![None; width]);
+		for (i, id) in cluster.members.iter().enumerate() {
+			grid[y + i / side][x + i % side] = Some(*id);
+		}
+		groups.push(GroupAnnotation {
+			label: label(i, cluster),
+			x: x as f32,
+			y: y as f32,
+			width: side as f32,
+			height: cluster.size.div_ceil(side.max(1)) as f32,
+		});
+
+		used_width = used_width.max(x + side);
+		x += side + 1;
+		shelf_height = shelf_height.max(side);
+	}
+
+	grid.iter_mut().for_each(|row| row.truncate(used_width));
+	(grid, groups)
+}
+
+// one pile per cluster, on a square grid
+pub fn piles(clusters: &[Cluster]) -> (Vec<(UuidString, f32, f32)>, Vec<GroupAnnotation>) {
+	let cells = (clusters.len() as f32).sqrt().ceil().max(1.0) as usize;
+	let centre = |c: usize| (c as f32 + 0.5) / cells as f32;
+
+	let positions = clusters
+		.iter()
+		.enumerate()
+		.flat_map(|(i, cluster)| {
+			let (x, y) = (centre(i % cells), centre(i / cells));
+			cluster.members.iter().map(move |id| (*id, x, y))
+		})
+		.collect();
+
+	let groups = clusters
+		.iter()
+		.enumerate()
+		.map(|(i, cluster)| GroupAnnotation {
+			label: label(i, cluster),
+			x: (i % cells) as f32 / cells as f32,
+			y: (i / cells) as f32 / cells as f32,
+			width: 1.0 / cells as f32,
+			height: 1.0 / cells as f32,
+		})
+		.collect();
+
+	(positions, groups)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::testing::{clusters as points, images_at};
+	use super::*;
+
+	const PER_CLUSTER: usize = 40;
+	const CENTRES: [(f32, f32); 3] = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+
+	fn opts(algorithm: ClusterAlgorithm) -> ClusterOptions {
+		ClusterOptions {
+			dist: DistanceFunctionVariants::DateTime,
+			algorithm,
+			arrangement: None,
+			seed: None,
+		}
+	}
+
+	// index of the input cluster an image was placed in, noise comes after the clusters
+	fn source(id: &UuidString) -> usize {
+		(id.0.as_u128() as usize - 1) / PER_CLUSTER
+	}
+
+	fn run(extra: &[(f32, f32)], algorithm: ClusterAlgorithm) -> Vec<Cluster> {
+		let mut points = points(&CENTRES, PER_CLUSTER, 1.0);
+		points.extend(extra);
+		let (images, dist) = images_at(&points);
+		clusters(dist, &images, opts(algorithm), &Progress::default()).unwrap()
+	}
+
+	fn assert_found(clusters: &[Cluster]) {
+		assert_eq!(clusters.iter().filter(|c| !c.noise).count(), CENTRES.len());
+		for cluster in clusters.iter().filter(|c| !c.noise) {
+			assert_eq!(cluster.size, PER_CLUSTER);
+			assert_eq!(cluster.members[0].0, cluster.representative.0);
+			let source = source(&cluster.representative);
+			assert!(cluster.members.iter().all(|id| self::source(id) == source));
+		}
+	}
+
+	#[test]
+	fn k_means_finds_separated_clusters() {
+		assert_found(&run(
+			&[],
+			ClusterAlgorithm::KMeans {
+				k: 3,
+				iterations: None,
+			},
+		));
+	}
+
+	#[test]
+	fn agglomerative_finds_separated_clusters() {
+		assert_found(&run(&[], ClusterAlgorithm::Agglomerative { k: 3 }));
+	}
+
+	#[test]
+	fn dbscan_finds_separated_clusters_and_noise() {
+		let clusters = run(
+			&[(50.0, 50.0), (-50.0, 30.0)],
+			ClusterAlgorithm::Dbscan {
+				eps: 1.5,
+				min_points: None,
+			},
+		);
+		assert_found(&clusters);
+
+		let noise = clusters.last().unwrap();
+		assert!(noise.noise);
+		assert_eq!(noise.size, 2);
+		assert!(noise.members.iter().all(|id| source(id) == CENTRES.len()));
+	}
+
+	#[test]
+	fn blocks_hold_every_member_once() {
+		let clusters = run(
+			&[],
+			ClusterAlgorithm::KMeans {
+				k: 3,
+				iterations: None,
+			},
+		);
+		let (grid, groups) = blocks(&clusters);
+		assert_eq!(groups.len(), clusters.len());
+
+		let placed = grid.iter().flatten().flatten().map(|id| id.0).collect_vec();
+		assert_eq!(placed.len(), CENTRES.len() * PER_CLUSTER);
+		assert_eq!(placed.iter().unique().count(), placed.len());
+
+		// every block only holds members of its own cluster
+		for (group, cluster) in groups.iter().zip(&clusters) {
+			let (x, y) = (group.x as usize, group.y as usize);
+			let (width, height) = (group.width as usize, group.height as usize);
+			let block = grid[y..y + height]
+				.iter()
+				.flat_map(|row| row[x..x + width].iter().flatten().map(|id| id.0))
+				.collect_vec();
+			assert_eq!(block.len(), cluster.size);
+			assert!(block
+				.iter()
+				.all(|id| cluster.members.iter().any(|m| m.0 == *id)));
+		}
+	}
+}
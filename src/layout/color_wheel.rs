@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::annotations::{Annotations, LegendEntry};
+use super::jobs::Progress;
+use super::snap::snap_to_grid;
+use super::UuidString;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorWheelRadius {
+	// dull colours inside, vivid colours at the rim
+	#[default]
+	Saturation,
+	// dark colours in the centre, light colours at the rim
+	Lightness,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ColorWheelOptions {
+	pub radius: Option<ColorWheelRadius>,
+	// number of grid cells along each axis to snap positions to, so images of similar colour don't overlap
+	// at most 4096
+	pub snap: Option<u32>,
+}
+
+// hue in radians, HSV saturation and HSL lightness, all but the hue in 0..1
+fn hsl((r, g, b): (u8, u8, u8)) -> (f32, f32, f32) {
+	let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+	let max = r.max(g).max(b);
+	let min = r.min(g).min(b);
+	let chroma = max - min;
+
+	let hue = if chroma == 0.0 {
+		0.0
+	} else if max == r {
+		((g - b) / chroma).rem_euclid(6.0)
+	} else if max == g {
+		(b - r) / chroma + 2.0
+	} else {
+		(r - g) / chroma + 4.0
+	};
+
+	let saturation = match max > 0.0 {
+		true => chroma / max,
+		false => 0.0,
+	};
+
+	(
+		hue * std::f32::consts::FRAC_PI_3,
+		saturation,
+		(max + min) / 2.0,
+	)
+}
+
+// dominant colours with less chroma than this have no meaningful hue
+const LOW_CHROMA: u8 = 24;
+// colours take the ring outside this radius; greys fill the disc inside it
+const INNER_RADIUS: f32 = 0.3;
+
+// where the grey of the given rank goes in the unit disc, ordered top to bottom
+// the height cuts off the rank's share of the disc's area above it, and the golden
+// ratio scatters greys of neighbouring ranks across the width of the disc at that height
+fn grey_position(rank: usize, len: usize) -> (f32, f32) {
+	let share = (rank as f32 + 0.5) / len as f32;
+	let area_above = |h: f32| (h.acos() - h * (1.0 - h * h).sqrt()) / std::f32::consts::PI;
+	let (mut low, mut high) = (-1.0f32, 1.0f32);
+	for _ in 0..24 {
+		let mid = (low + high) / 2.0;
+		match area_above(mid) < share {
+			true => high = mid,
+			false => low = mid,
+		}
+	}
+	let h = (low + high) / 2.0;
+	let x = (rank as f32 * 0.618_034).fract() * 2.0 - 1.0;
+	(x * (1.0 - h * h).sqrt(), -h)
+}
+
+// the hue of the dominant colour is the angle; red points right, hues go round counter-clockwise
+// greys fill the disc in the centre, from light at the top to dark at the bottom
+// images without a palette are left out
+pub fn color_wheel(
+	metadata: &[Image],
+	opts: ColorWheelOptions,
+	progress: &Progress,
+) -> Result<Vec<(UuidString, f32, f32)>> {
+	let mut res = Vec::new();
+	let mut greys = Vec::new();
+	for img in metadata {
+		let Some(&(r, g, b)) = img.metadata.palette.as_ref().and_then(|p| p.first()) else {
+			continue;
+		};
+		let (hue, saturation, lightness) = hsl((r, g, b));
+		if r.max(g).max(b) - r.min(g).min(b) < LOW_CHROMA {
+			greys.push((img.id, lightness));
+			continue;
+		}
+
+		let radius = match opts.radius.unwrap_or_default() {
+			ColorWheelRadius::Saturation => saturation,
+			ColorWheelRadius::Lightness => lightness,
+		};
+		let radius = INNER_RADIUS + (1.0 - INNER_RADIUS) * radius;
+		res.push((
+			UuidString(img.id),
+			0.5 + radius * hue.cos() / 2.0,
+			0.5 - radius * hue.sin() / 2.0,
+		));
+	}
+
+	// by rank rather than by lightness, so greys of the same lightness don't pile up
+	greys.sort_by(|a, b| b.1.total_cmp(&a.1));
+	let len = greys.len();
+	res.extend(greys.into_iter().enumerate().map(|(i, (id, _))| {
+		let (x, y) = grey_position(i, len);
+		(
+			UuidString(id),
+			0.5 + INNER_RADIUS * x / 2.0,
+			0.5 + INNER_RADIUS * y / 2.0,
+		)
+	}));
+
+	if let Some(cells) = opts.snap {
+		snap_to_grid(&mut res, cells, progress)?;
+	}
+
+	Ok(res)
+}
+
+// primary and secondary hues at the rim, what the inner edge of the ring stands for
+// and the ends of the grey axis
+pub fn annotations(opts: ColorWheelOptions) -> Annotations {
+	let hues = [
+		("red", (255, 0, 0)),
+		("yellow", (255, 255, 0)),
+		("green", (0, 255, 0)),
+		("cyan", (0, 255, 255)),
+		("blue", (0, 0, 255)),
+		("magenta", (255, 0, 255)),
+	];
+
+	let mut legend = hues
+		.into_iter()
+		.map(|(label, color)| {
+			let (hue, _, _) = hsl(color);
+			LegendEntry {
+				label: label.into(),
+				color: Some(color),
+				position: Some((0.5 + hue.cos() / 2.0, 0.5 - hue.sin() / 2.0)),
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let inner = match opts.radius.unwrap_or_default() {
+		ColorWheelRadius::Saturation => ("dull", (160, 128, 128)),
+		ColorWheelRadius::Lightness => ("dark", (64, 0, 0)),
+	};
+	legend.push(LegendEntry {
+		label: inner.0.into(),
+		color: Some(inner.1),
+		position: Some((0.5 + INNER_RADIUS / 2.0, 0.5)),
+	});
+	legend.push(LegendEntry {
+		label: "white".into(),
+		color: Some((255, 255, 255)),
+		position: Some((0.5, 0.5 - INNER_RADIUS / 2.0)),
+	});
+	legend.push(LegendEntry {
+		label: "black".into(),
+		color: Some((0, 0, 0)),
+		position: Some((0.5, 0.5 + INNER_RADIUS / 2.0)),
+	});
+
+	Annotations {
+		legend: Some(legend),
+		..Default::default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use super::super::testing::images_at;
+	use super::*;
+
+	fn images(palettes: &[(u8, u8, u8)]) -> Vec<Image> {
+		let (mut images, _) = images_at(&vec![(0.0, 0.0); palettes.len()]);
+		for (img, color) in images.iter_mut().zip(palettes) {
+			img.metadata.palette = Some(vec![*color]);
+		}
+		images
+	}
+
+	fn radius(x: f32, y: f32) -> f32 {
+		2.0 * (x - 0.5).hypot(y - 0.5)
+	}
+
+	#[test]
+	fn greys_fill_the_centre_ordered_by_lightness() {
+		// many greys of only a few shades, and a few colours
+		let shades = [(250, 250, 250), (128, 130, 128), (10, 10, 12)];
+		let mut palettes = (0..3000).map(|i| shades[i % 3]).collect::<Vec<_>>();
+		palettes.extend([(255, 0, 0), (0, 0, 255), (140, 128, 100)]);
+		let images = images(&palettes);
+		let opts = ColorWheelOptions {
+			radius: None,
+			snap: None,
+		};
+		let res = color_wheel(&images, opts, &Progress::default()).unwrap();
+		assert_eq!(res.len(), palettes.len());
+
+		let (colours, greys): (Vec<_>, Vec<_>) = res.iter().partition(|(id, _, _)| {
+			let i = images.iter().position(|img| img.id == id.0).unwrap();
+			i >= 3000
+		});
+		assert!(colours
+			.iter()
+			.all(|(_, x, y)| radius(*x, *y) >= INNER_RADIUS - 1e-4));
+		assert!(greys
+			.iter()
+			.all(|(_, x, y)| radius(*x, *y) <= INNER_RADIUS + 1e-4));
+
+		// no two greys share a position, and lighter ones are higher up
+		let distinct = greys
+			.iter()
+			.map(|(_, x, y)| (x.to_bits(), y.to_bits()))
+			.collect::<HashSet<_>>();
+		assert_eq!(distinct.len(), greys.len());
+		let mut greys = greys
+			.iter()
+			.map(|(id, _, y)| {
+				let i = images.iter().position(|img| img.id == id.0).unwrap();
+				(*y, hsl(palettes[i]).2)
+			})
+			.collect::<Vec<_>>();
+		greys.sort_by(|a, b| a.0.total_cmp(&b.0));
+		assert!(greys.windows(2).all(|w| w[0].1 >= w[1].1));
+	}
+
+	#[test]
+	fn snapped_greys_fill_the_centre() {
+		let images = images(&vec![(128, 128, 128); 600]);
+		let opts = ColorWheelOptions {
+			radius: Some(ColorWheelRadius::Lightness),
+			snap: Some(100),
+		};
+		let res = color_wheel(&images, opts, &Progress::default()).unwrap();
+
+		let cells = res
+			.iter()
+			.map(|(_, x, y)| ((x * 100.0) as u32, (y * 100.0) as u32))
+			.collect::<HashSet<_>>();
+		assert_eq!(cells.len(), res.len());
+		// the inner disc is 15 cells in radius, room for about 700 images
+		assert!(res
+			.iter()
+			.all(|(_, x, y)| radius(*x, *y) < INNER_RADIUS + 0.02));
+	}
+}
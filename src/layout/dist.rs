@@ -1,6 +1,10 @@
 use serde::{self, Deserialize, Serialize};
 
+use crate::capture::CaptureInfo;
+use crate::color::{ciede2000, palette_emd};
 use crate::db::Image;
+use crate::phash::{hamming, HashKind};
+use crate::quality::QualityMetrics;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -8,13 +12,108 @@ use crate::db::Image;
 pub enum DistanceFunctionVariants {
 	Palette,
 	PaletteCos,
+	Ciede2000,
+	PaletteEmd,
+	HistogramIntersection,
 	DateTime,
+	Iso,
+	FNumber,
+	ExposureTime,
+	FocalLength,
+	// hashes have no order, only a distance; to sort or expand a grid by similarity,
+	// use comparative_dist with the image to compare to
+	PerceptualHash {
+		#[serde(default)]
+		hash: HashKind,
+	},
+	Sharpness,
+	Noise,
+	Brightness,
+	Contrast,
+	Clipping,
+	Histogram,
+}
+
+impl DistanceFunctionVariants {
+	// whether the distance has a sign, i.e. images can be sorted by its key
+	// unsigned distances are rejected by signed_dist, for sorting and grid expansion alike
+	pub fn is_signed(&self) -> bool {
+		matches!(
+			self,
+			Self::Palette
+				| Self::DateTime
+				| Self::Iso | Self::FNumber
+				| Self::ExposureTime
+				| Self::FocalLength
+				| Self::Sharpness
+				| Self::Noise
+				| Self::Brightness
+				| Self::Contrast
+				| Self::Clipping
+		)
+	}
+
+	pub fn get_function(&self) -> Box<dyn DistanceFunction> {
+		match self {
+			Self::Palette => Box::new(PaletteDist),
+			Self::PaletteCos => Box::new(PaletteCosDist),
+			Self::Ciede2000 => Box::new(Ciede2000Dist),
+			Self::PaletteEmd => Box::new(PaletteEmdDist),
+			Self::HistogramIntersection => Box::new(HistogramIntersectionDist),
+			Self::DateTime => Box::new(DateTimeDist),
+			Self::Iso => Box::new(IsoDist),
+			Self::FNumber => Box::new(FNumberDist),
+			Self::ExposureTime => Box::new(ExposureTimeDist),
+			Self::FocalLength => Box::new(FocalLengthDist),
+			Self::PerceptualHash { hash } => Box::new(PerceptualHashDist { hash: *hash }),
+			Self::Sharpness => Box::new(SharpnessDist),
+			Self::Noise => Box::new(NoiseDist),
+			Self::Brightness => Box::new(BrightnessDist),
+			Self::Contrast => Box::new(ContrastDist),
+			Self::Clipping => Box::new(ClippingDist),
+			Self::Histogram => Box::new(HistogramDist),
+		}
+	}
 }
 
 pub trait DistanceFunction: Send + Sync {
 	fn dist(&self, m1: &Image, m2: &Image) -> f32;
+
+	// the value signed distances order images by; the difference of keys for distances
+	// comparing a single value, None for unsigned distances and images without the value
+	fn key(&self, _m: &Image) -> Option<f64> {
+		None
+	}
+}
+
+impl<D: DistanceFunction> DistanceFunction for &D {
+	#[inline(always)]
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		(**self).dist(m1, m2)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		(**self).key(m)
+	}
+}
+
+impl DistanceFunction for Box<dyn DistanceFunction> {
+	#[inline(always)]
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		(**self).dist(m1, m2)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		(**self).key(m)
+	}
 }
 
+fn luminance(c: &(u8, u8, u8)) -> f32 {
+	0.2126 * c.0 as f32 + 0.7152 * c.1 as f32 + 0.0722 * c.2 as f32
+}
+
+// weighted sum of the euclidean distances between palette entries, dominant colours count most
+// signed by the difference in luminance of the dominant colours, so it orders from dark to bright
 pub struct PaletteDist;
 
 impl DistanceFunction for PaletteDist {
@@ -22,26 +121,36 @@ impl DistanceFunction for PaletteDist {
 	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
 		match (&m1.metadata.palette, &m2.metadata.palette) {
 			(None, _) | (_, None) => f32::INFINITY,
+			(Some(p1), _) | (_, Some(p1)) if p1.is_empty() => f32::INFINITY,
 			(Some(p1), Some(p2)) => {
 				let mut sum = 0.0;
 				let mut multiplier = 1.0;
-				let start = p1.len().min(p2.len()) - 1;
 
-				for i in 0..=0 {
-					let r = p1[i].0 as f32 - p2[i].0 as f32;
-					let g = p1[i].1 as f32 - p2[i].1 as f32;
-					let b = p1[i].2 as f32 - p2[i].2 as f32;
+				for (c1, c2) in p1.iter().zip(p2.iter()) {
+					let r = c1.0 as f32 - c2.0 as f32;
+					let g = c1.1 as f32 - c2.1 as f32;
+					let b = c1.2 as f32 - c2.2 as f32;
 
-					sum += (r + g + b) * multiplier;
-					multiplier *= 100.0;
+					sum += (r * r + g * g + b * b).sqrt() * multiplier;
+					multiplier /= 2.0;
 				}
 
-				sum
+				match luminance(&p1[0]) < luminance(&p2[0]) {
+					true => -sum,
+					false => sum,
+				}
 			}
 		}
 	}
+
+	// luminance of the dominant colour
+	fn key(&self, m: &Image) -> Option<f64> {
+		let palette = m.metadata.palette.as_ref()?;
+		palette.first().map(|c| luminance(c) as f64)
+	}
 }
 
+// cosine distance between the palettes as flat RGB vectors
 pub struct PaletteCosDist;
 
 impl DistanceFunction for PaletteCosDist {
@@ -49,7 +158,7 @@ impl DistanceFunction for PaletteCosDist {
 		match (&m1.metadata.palette, &m2.metadata.palette) {
 			(None, _) | (_, None) => f32::INFINITY,
 			(Some(p1), Some(p2)) => {
-				let mut count = 0.0;
+				let mut dot = 0.0;
 				let mut sq_a = 0.0;
 				let mut sq_b = 0.0;
 
@@ -57,12 +166,61 @@ impl DistanceFunction for PaletteCosDist {
 					let c1 = (c1.0 as f32, c1.1 as f32, c1.2 as f32);
 					let c2 = (c2.0 as f32, c2.1 as f32, c2.2 as f32);
 
-					count += c1.0 + c1.1 + c1.2 + c2.0 + c2.1 + c2.2;
+					dot += c1.0 * c2.0 + c1.1 * c2.1 + c1.2 * c2.2;
 					sq_a += c1.0.powi(2) + c1.1.powi(2) + c1.2.powi(2);
 					sq_b += c2.0.powi(2) + c2.1.powi(2) + c2.2.powi(2);
 				}
 
-				(count / sq_a.sqrt() * sq_b.sqrt()).recip()
+				// black palettes have no direction
+				match sq_a * sq_b > 0.0 {
+					true => 1.0 - dot / (sq_a.sqrt() * sq_b.sqrt()),
+					false => f32::INFINITY,
+				}
+			}
+		}
+	}
+}
+
+// CIEDE2000 between the dominant colours
+pub struct Ciede2000Dist;
+
+impl DistanceFunction for Ciede2000Dist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		let c1 = m1.metadata.lab_palette.as_ref().and_then(|p| p.first());
+		let c2 = m2.metadata.lab_palette.as_ref().and_then(|p| p.first());
+		match (c1, c2) {
+			(None, _) | (_, None) => f32::INFINITY,
+			(Some(c1), Some(c2)) => ciede2000(c1.lab(), c2.lab()),
+		}
+	}
+}
+
+// earth mover's distance between the weighted CIELAB palettes
+pub struct PaletteEmdDist;
+
+impl DistanceFunction for PaletteEmdDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		match (&m1.metadata.lab_palette, &m2.metadata.lab_palette) {
+			(None, _) | (_, None) => f32::INFINITY,
+			(Some(p1), Some(p2)) => palette_emd(p1, p2),
+		}
+	}
+}
+
+// one minus the overlap of the colour histograms
+pub struct HistogramIntersectionDist;
+
+impl DistanceFunction for HistogramIntersectionDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		match (&m1.metadata.color_histogram, &m2.metadata.color_histogram) {
+			(None, _) | (_, None) => f32::INFINITY,
+			(Some(h1), Some(h2)) => {
+				let intersection = h1
+					.iter()
+					.zip(h2.iter())
+					.map(|(a, b)| a.min(*b))
+					.sum::<f32>();
+				(1.0 - intersection).max(0.0)
 			}
 		}
 	}
@@ -77,4 +235,183 @@ impl DistanceFunction for DateTimeDist {
 			(Some(dt1), Some(dt2)) => (dt1.timestamp() - dt2.timestamp()) as f32,
 		}
 	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.date_time.map(|dt| dt.timestamp() as f64)
+	}
+}
+
+fn capture<F: Fn(&CaptureInfo) -> Option<f32>>(m: &Image, value: F) -> Option<f64> {
+	m.metadata
+		.capture
+		.as_ref()
+		.and_then(value)
+		.map(|v| v as f64)
+}
+
+// exposure settings are compared in stops, i.e. on a log scale
+fn stops<F: Fn(&CaptureInfo) -> Option<f32>>(m1: &Image, m2: &Image, value: F) -> f32 {
+	let v1 = m1.metadata.capture.as_ref().and_then(&value);
+	let v2 = m2.metadata.capture.as_ref().and_then(&value);
+	match (v1, v2) {
+		(None, _) | (_, None) => f32::INFINITY,
+		(Some(v1), Some(v2)) => v1.log2() - v2.log2(),
+	}
+}
+
+pub struct IsoDist;
+
+impl DistanceFunction for IsoDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		stops(m1, m2, |c| c.iso.map(|iso| iso as f32))
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		capture(m, |c| c.iso.map(|iso| iso as f32))
+	}
+}
+
+pub struct FNumberDist;
+
+impl DistanceFunction for FNumberDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		// a stop is a factor of sqrt(2) in f-number
+		2.0 * stops(m1, m2, |c| c.f_number)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		capture(m, |c| c.f_number)
+	}
+}
+
+pub struct ExposureTimeDist;
+
+impl DistanceFunction for ExposureTimeDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		stops(m1, m2, |c| c.exposure_time)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		capture(m, |c| c.exposure_time)
+	}
+}
+
+pub struct FocalLengthDist;
+
+impl DistanceFunction for FocalLengthDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		// the 35mm equivalent is comparable across sensor sizes
+		stops(m1, m2, |c| {
+			c.focal_length_35mm.map(|f| f as f32).or(c.focal_length)
+		})
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		capture(m, |c| {
+			c.focal_length_35mm.map(|f| f as f32).or(c.focal_length)
+		})
+	}
+}
+
+// number of differing bits
+pub struct PerceptualHashDist {
+	pub hash: HashKind,
+}
+
+impl DistanceFunction for PerceptualHashDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		match (&m1.metadata.hashes, &m2.metadata.hashes) {
+			(None, _) | (_, None) => f32::INFINITY,
+			(Some(h1), Some(h2)) => hamming(h1.get(self.hash), h2.get(self.hash)) as f32,
+		}
+	}
+}
+
+fn quality_diff<F: Fn(&QualityMetrics) -> f32>(m1: &Image, m2: &Image, value: F) -> f32 {
+	match (&m1.metadata.quality, &m2.metadata.quality) {
+		(None, _) | (_, None) => f32::INFINITY,
+		(Some(q1), Some(q2)) => value(q1) - value(q2),
+	}
+}
+
+pub struct SharpnessDist;
+
+impl DistanceFunction for SharpnessDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		// the variance of the Laplacian spans orders of magnitude
+		quality_diff(m1, m2, |q| q.sharpness.max(f32::MIN_POSITIVE).log2())
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.quality.as_ref().map(|q| q.sharpness as f64)
+	}
+}
+
+pub struct NoiseDist;
+
+impl DistanceFunction for NoiseDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		quality_diff(m1, m2, |q| q.noise)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.quality.as_ref().map(|q| q.noise as f64)
+	}
+}
+
+pub struct BrightnessDist;
+
+impl DistanceFunction for BrightnessDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		quality_diff(m1, m2, |q| q.brightness)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.quality.as_ref().map(|q| q.brightness as f64)
+	}
+}
+
+pub struct ContrastDist;
+
+impl DistanceFunction for ContrastDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		quality_diff(m1, m2, |q| q.contrast)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.quality.as_ref().map(|q| q.contrast as f64)
+	}
+}
+
+pub struct ClippingDist;
+
+impl DistanceFunction for ClippingDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		quality_diff(m1, m2, |q| q.clipping)
+	}
+
+	fn key(&self, m: &Image) -> Option<f64> {
+		m.metadata.quality.as_ref().map(|q| q.clipping as f64)
+	}
+}
+
+// earth mover's distance between luminance histograms; in 1D, the area between their CDFs
+pub struct HistogramDist;
+
+impl DistanceFunction for HistogramDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		match (&m1.metadata.quality, &m2.metadata.quality) {
+			(None, _) | (_, None) => f32::INFINITY,
+			(Some(q1), Some(q2)) => {
+				let mut cdf = 0.0;
+				let mut sum = 0.0;
+				for (h1, h2) in q1.histogram.iter().zip(q2.histogram.iter()) {
+					cdf += h1 - h2;
+					sum += f32::abs(cdf);
+				}
+
+				sum / q1.histogram.len().max(1) as f32
+			}
+		}
+	}
 }
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureVariants {
+	// dominant colour in CIELAB
+	DominantColor,
+	ColorHistogram,
+	DateTime,
+	Sharpness,
+	Noise,
+	Brightness,
+	Contrast,
+	LuminanceHistogram,
+}
+
+impl FeatureVariants {
+	fn extract(&self, img: &Image) -> Option<Vec<f64>> {
+		let metadata = &img.metadata;
+		let quality = metadata.quality.as_ref();
+		match self {
+			Self::DominantColor => metadata
+				.lab_palette
+				.as_ref()
+				.and_then(|p| p.first())
+				.map(|c| vec![c.l as f64, c.a as f64, c.b as f64]),
+			Self::ColorHistogram => metadata
+				.color_histogram
+				.as_ref()
+				.map(|h| h.iter().map(|v| *v as f64).collect()),
+			Self::DateTime => metadata.date_time.map(|dt| vec![dt.timestamp() as f64]),
+			Self::Sharpness => {
+				quality.map(|q| vec![q.sharpness.max(f32::MIN_POSITIVE).log2() as f64])
+			}
+			Self::Noise => quality.map(|q| vec![q.noise as f64]),
+			Self::Brightness => quality.map(|q| vec![q.brightness as f64]),
+			Self::Contrast => quality.map(|q| vec![q.contrast as f64]),
+			Self::LuminanceHistogram => {
+				quality.map(|q| q.histogram.iter().map(|v| *v as f64).collect())
+			}
+		}
+	}
+}
+
+// one row of `dim` values per image; each feature is centred and scaled to a total variance of 1,
+// so features with many dimensions or large units don't outweigh the others
+// missing values are replaced by the mean, i.e. 0
+pub fn feature_vectors(images: &[Image], features: &[FeatureVariants]) -> (Vec<f32>, usize) {
+	let columns = features
+		.iter()
+		.map(|feature| {
+			let values = images
+				.iter()
+				.map(|img| feature.extract(img))
+				.collect::<Vec<_>>();
+			let dim = values.iter().flatten().map(|v| v.len()).max().unwrap_or(0);
+
+			let mut mean = vec![0.0; dim];
+			let mut count = 0.0;
+			for v in values.iter().flatten().filter(|v| v.len() == dim) {
+				mean.iter_mut().zip(v).for_each(|(m, v)| *m += v);
+				count += 1.0;
+			}
+			mean.iter_mut().for_each(|m| *m /= f64::max(count, 1.0));
+
+			let centred = values
+				.into_iter()
+				.map(|v| match v {
+					Some(v) if v.len() == dim => v.iter().zip(&mean).map(|(v, m)| v - m).collect(),
+					_ => vec![0.0; dim],
+				})
+				.collect::<Vec<Vec<f64>>>();
+
+			let variance =
+				centred.iter().flatten().map(|v| v * v).sum::<f64>() / images.len().max(1) as f64;
+			let scale = match variance > 0.0 {
+				true => variance.sqrt().recip(),
+				false => 0.0,
+			};
+
+			(centred, dim, scale)
+		})
+		.collect::<Vec<_>>();
+
+	let dim = columns.iter().map(|(_, dim, _)| dim).sum();
+	let mut res = Vec::with_capacity(images.len() * dim);
+	for i in 0..images.len() {
+		for (values, _, scale) in columns.iter() {
+			res.extend(values[i].iter().map(|v| (v * scale) as f32));
+		}
+	}
+
+	(res, dim)
+}
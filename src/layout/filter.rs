@@ -1,33 +1,94 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 use crate::db::Image;
 
+// inclusive bounds, either of which may be left open
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Range<T> {
+	min: Option<T>,
+	max: Option<T>,
+}
+
+impl<T: PartialOrd> Range<T> {
+	// images without the value never match
+	fn contains(&self, value: Option<T>) -> bool {
+		match value {
+			None => false,
+			Some(v) => {
+				self.min.as_ref().is_none_or(|min| v >= *min)
+					&& self.max.as_ref().is_none_or(|max| v <= *max)
+			}
+		}
+	}
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Filter {
 	has_metadata: Option<Vec<String>>,
+	// any of the given values matches
+	make: Option<Vec<String>>,
+	model: Option<Vec<String>>,
+	lens: Option<Vec<String>>,
+	iso: Option<Range<u32>>,
+	f_number: Option<Range<f32>>,
+	exposure_time: Option<Range<f32>>,
+	focal_length: Option<Range<f32>>,
+	date_time: Option<Range<NaiveDateTime>>,
 	pub limit: Option<usize>,
 }
 
+fn one_of(values: &Option<Vec<String>>, value: Option<&String>) -> bool {
+	match values {
+		None => true,
+		Some(values) => value.is_some_and(|v| values.contains(v)),
+	}
+}
+
+fn in_range<T: PartialOrd>(range: &Option<Range<T>>, value: Option<T>) -> bool {
+	match range {
+		None => true,
+		Some(range) => range.contains(value),
+	}
+}
+
 impl Filter {
 	pub fn filter(&self, m: &Image) -> bool {
+		let capture = m.metadata.capture.as_ref();
+
 		if let Some(ref has_metadata) = self.has_metadata {
 			for hm in has_metadata.iter() {
 				match hm.as_str() {
-					"date_time" => {
-						if m.metadata.date_time.is_none() {
-							return false;
-						}
+					"date_time" if m.metadata.date_time.is_none() => return false,
+					"palette" if m.metadata.palette.is_none() => return false,
+					"lab_palette" if m.metadata.lab_palette.is_none() => return false,
+					"color_histogram" if m.metadata.color_histogram.is_none() => return false,
+					"gps" if m.metadata.gps.is_none() => return false,
+					"hashes" if m.metadata.hashes.is_none() => return false,
+					"quality" if m.metadata.quality.is_none() => return false,
+					"make" if capture.and_then(|c| c.make.as_ref()).is_none() => return false,
+					"model" if capture.and_then(|c| c.model.as_ref()).is_none() => return false,
+					"lens" if capture.and_then(|c| c.lens.as_ref()).is_none() => return false,
+					"iso" if capture.and_then(|c| c.iso).is_none() => return false,
+					"f_number" if capture.and_then(|c| c.f_number).is_none() => return false,
+					"exposure_time" if capture.and_then(|c| c.exposure_time).is_none() => {
+						return false
 					}
-					"palette" => {
-						if m.metadata.palette.is_none() {
-							return false;
-						}
+					"focal_length" if capture.and_then(|c| c.focal_length).is_none() => {
+						return false
 					}
 					_ => {}
 				}
 			}
 		}
 
-		true
+		one_of(&self.make, capture.and_then(|c| c.make.as_ref()))
+			&& one_of(&self.model, capture.and_then(|c| c.model.as_ref()))
+			&& one_of(&self.lens, capture.and_then(|c| c.lens.as_ref()))
+			&& in_range(&self.iso, capture.and_then(|c| c.iso))
+			&& in_range(&self.f_number, capture.and_then(|c| c.f_number))
+			&& in_range(&self.exposure_time, capture.and_then(|c| c.exposure_time))
+			&& in_range(&self.focal_length, capture.and_then(|c| c.focal_length))
+			&& in_range(&self.date_time, m.metadata.date_time)
 	}
 }
@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::features::{feature_vectors, FeatureVariants};
+use super::jobs::Progress;
+use super::lap::assign;
+use super::UuidString;
+
+// number of cells reassigned together, sampled from the same swap area
+const SWAP_CANDIDATES: usize = 16;
+// swap areas are about twice the filter radius across, but at least this
+const MIN_SWAP_SIZE: usize = 4;
+// the filter radius shrinks by this factor every iteration, until it reaches 1
+const RADIUS_DECAY: f32 = 0.9;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SortedGridOptions {
+	pub features: Vec<FeatureVariants>,
+	// the initial arrangement is random; the same seed gives the same grid
+	pub seed: Option<u64>,
+}
+
+// consecutive ranges of at most size, the first one ends at the offset
+fn blocks(len: usize, size: usize, offset: usize) -> Vec<Range<usize>> {
+	let mut res = vec![];
+	let mut start = 0;
+	let mut end = match offset {
+		0 => size,
+		_ => offset,
+	};
+	while start < len {
+		res.push(start..end.min(len));
+		start = end;
+		end += size;
+	}
+	res
+}
+
+// mean of the vectors in a window around each cell; empty cells don't count
+fn box_filter(
+	cells: &[Option<usize>],
+	vectors: &[f32],
+	dim: usize,
+	(rows, cols): (usize, usize),
+	radius: usize,
+) -> Vec<f32> {
+	// running sums along the rows, then along the columns; the last value is the count
+	let filter = |input: &[f32], len: usize, stride: usize, lines: usize, line_stride: usize| {
+		let mut res = vec![0.0; input.len()];
+		let mut sum = vec![0.0; dim + 1];
+		for line in 0..lines {
+			sum.iter_mut().for_each(|s| *s = 0.0);
+			let at = |i: usize| (line * line_stride + i * stride) * (dim + 1);
+			for i in 0..len.min(radius) {
+				sum.iter_mut()
+					.zip(&input[at(i)..])
+					.for_each(|(s, v)| *s += v);
+			}
+			for i in 0..len {
+				if i + radius < len {
+					sum.iter_mut()
+						.zip(&input[at(i + radius)..])
+						.for_each(|(s, v)| *s += v);
+				}
+				res[at(i)..at(i) + dim + 1].copy_from_slice(&sum);
+				if i >= radius {
+					sum.iter_mut()
+						.zip(&input[at(i - radius)..])
+						.for_each(|(s, v)| *s -= v);
+				}
+			}
+		}
+		res
+	};
+
+	let mut input = vec![0.0; cells.len() * (dim + 1)];
+	for (cell, img) in cells.iter().enumerate() {
+		if let Some(img) = img {
+			let at = cell * (dim + 1);
+			input[at..at + dim].copy_from_slice(&vectors[img * dim..(img + 1) * dim]);
+			input[at + dim] = 1.0;
+		}
+	}
+
+	let horizontal = filter(&input, cols, 1, rows, cols);
+	let sums = filter(&horizontal, rows, cols, cols, 1);
+
+	sums.chunks(dim + 1)
+		.flat_map(|sum| {
+			let count = sum[dim].max(1.0);
+			sum[..dim].iter().map(move |s| s / count)
+		})
+		.collect()
+}
+
+// fast linear assignment sorting: random samples of cells within a swap area are reassigned,
+// towards the cells whose smoothed neighbourhood resembles them most
+// the smoothing radius and with it the swap area shrink over time, so images first move
+// across the whole grid and the grid goes from coarse to fine order
+pub fn sorted_grid(
+	images: &[Image],
+	opts: &SortedGridOptions,
+	progress: &Progress,
+) -> Result<Vec<Vec<Option<UuidString>>>> {
+	let (vectors, dim) = feature_vectors(images, &opts.features);
+
+	let cols = (images.len() as f32).sqrt().ceil().max(1.0) as usize;
+	let rows = images.len().div_ceil(cols);
+
+	let mut rng = StdRng::seed_from_u64(opts.seed.unwrap_or_default());
+	let mut cells = (0..rows * cols)
+		.map(|i| (i < images.len()).then_some(i))
+		.collect_vec();
+	cells.shuffle(&mut rng);
+
+	let dist = |img: usize, target: &[f32]| {
+		vectors[img * dim..(img + 1) * dim]
+			.iter()
+			.zip(target)
+			.map(|(a, b)| (a - b) * (a - b))
+			.sum::<f32>()
+	};
+
+	let mut radius = rows.max(cols) as f32 / 2.0;
+	loop {
+		let filtered = box_filter(&cells, &vectors, dim, (rows, cols), radius as usize);
+
+		// areas start at a random offset, so images can move across area borders over time
+		let size = (2 * radius as usize + 1).max(MIN_SWAP_SIZE);
+		let (size_y, size_x) = (size.min(rows).max(1), size.min(cols).max(1));
+		let (offset_y, offset_x) = (rng.gen_range(0..size_y), rng.gen_range(0..size_x));
+		let area_cols = blocks(cols, size_x, offset_x);
+		for (area_rows, area_cols) in blocks(rows, size_y, offset_y)
+			.into_iter()
+			.cartesian_product(area_cols)
+		{
+			progress.check()?;
+
+			let mut area = area_rows
+				.cartesian_product(area_cols)
+				.map(|(r, c)| r * cols + c)
+				.collect_vec();
+			area.shuffle(&mut rng);
+
+			for candidates in area.chunks(SWAP_CANDIDATES) {
+				// empty cells fit anywhere
+				let cost = candidates
+					.iter()
+					.map(|&from| {
+						candidates
+							.iter()
+							.map(|&to| match cells[from] {
+								Some(img) => dist(img, &filtered[to * dim..(to + 1) * dim]),
+								None => 0.0,
+							})
+							.collect_vec()
+					})
+					.collect_vec();
+
+				let before = candidates.iter().map(|&cell| cells[cell]).collect_vec();
+				for (img, to) in before.into_iter().zip(assign(&cost)) {
+					cells[candidates[to]] = img;
+				}
+			}
+		}
+
+		if radius <= 1.0 {
+			break;
+		}
+		radius = (radius * RADIUS_DECAY).max(1.0);
+	}
+
+	Ok(cells
+		.chunks(cols)
+		.map(|row| {
+			row.iter()
+				.map(|img| img.map(|i| UuidString(images[i].id)))
+				.collect()
+		})
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use uuid::Uuid;
+
+	use super::super::testing::images_at;
+	use super::*;
+	use crate::color::LabColor;
+
+	// images of random colours, by id
+	fn images(count: usize) -> (Vec<Image>, HashMap<Uuid, (f32, f32)>) {
+		let mut rng = StdRng::seed_from_u64(1);
+		let (mut images, _) = images_at(&vec![(0.0, 0.0); count]);
+		let mut colors = HashMap::new();
+		for img in &mut images {
+			let (a, b) = (rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0));
+			img.metadata.lab_palette = Some(vec![LabColor {
+				l: 50.0,
+				a,
+				b,
+				weight: 1.0,
+			}]);
+			colors.insert(img.id, (a, b));
+		}
+		(images, colors)
+	}
+
+	// mean colour distance between horizontally and vertically adjacent images
+	fn neighbour_distance(
+		grid: &[Vec<Option<UuidString>>],
+		colors: &HashMap<Uuid, (f32, f32)>,
+	) -> f32 {
+		let color = |row: usize, col: usize| {
+			let id = grid.get(row)?.get(col)?.as_ref()?;
+			Some(colors[&id.0])
+		};
+		let mut dists = vec![];
+		for (row, col) in (0..grid.len()).cartesian_product(0..grid[0].len()) {
+			for next in [color(row, col + 1), color(row + 1, col)] {
+				if let (Some(c), Some(n)) = (color(row, col), next) {
+					dists.push((c.0 - n.0).hypot(c.1 - n.1));
+				}
+			}
+		}
+		dists.iter().sum::<f32>() / dists.len() as f32
+	}
+
+	fn opts(seed: u64) -> SortedGridOptions {
+		SortedGridOptions {
+			features: vec![FeatureVariants::DominantColor],
+			seed: Some(seed),
+		}
+	}
+
+	#[test]
+	fn similar_images_become_neighbours() {
+		let (images, colors) = images(300);
+		let grid = sorted_grid(&images, &opts(0), &Progress::default()).unwrap();
+
+		let placed = grid.iter().flatten().flatten().map(|id| id.0).collect_vec();
+		assert_eq!(placed.len(), images.len());
+		assert_eq!(placed.iter().unique().count(), images.len());
+
+		// the images in the order they were given, i.e. randomly arranged
+		let unsorted = images
+			.chunks(grid[0].len())
+			.map(|row| row.iter().map(|img| Some(UuidString(img.id))).collect())
+			.collect_vec();
+		let (sorted, unsorted) = (
+			neighbour_distance(&grid, &colors),
+			neighbour_distance(&unsorted, &colors),
+		);
+		// 300 colours spread evenly over the 100 x 100 square would be about 6 apart
+		assert!(sorted < 10.0 && sorted < unsorted / 4.0);
+	}
+
+	#[test]
+	fn same_seed_same_grid() {
+		let (images, _) = images(50);
+		let ids = |seed| {
+			sorted_grid(&images, &opts(seed), &Progress::default())
+				.unwrap()
+				.into_iter()
+				.flatten()
+				.map(|id| id.map(|id| id.0))
+				.collect_vec()
+		};
+		assert_eq!(ids(3), ids(3));
+		assert_ne!(ids(3), ids(4));
+	}
+
+	#[test]
+	fn cancelled() {
+		let progress = Progress::default();
+		progress.cancel();
+		let (images, _) = images(10);
+		assert!(sorted_grid(&images, &opts(0), &progress).is_err());
+	}
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::Serialize;
+use tokio::sync::{watch, Semaphore};
+use uuid::Uuid;
+
+use crate::err::{Error, Result};
+
+use super::{normalize_positions, UuidString};
+
+// partial embeddings are sent at most every this many epochs
+const PARTIAL_INTERVAL: u32 = 10;
+// finished jobs can be looked up for this long
+const JOB_RETENTION: Duration = Duration::from_secs(10 * 60);
+// jobs beyond this many wait for others to finish
+const MAX_RUNNING_JOBS: usize = 2;
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "state")]
+pub enum JobState {
+	// waiting for other jobs to finish
+	#[default]
+	Queued,
+	Running,
+	// the layout is cached and can be fetched or saved by its id
+	Done {
+		layout_id: UuidString,
+	},
+	Failed {
+		error: String,
+	},
+	Cancelled,
+}
+
+impl JobState {
+	fn is_finished(&self) -> bool {
+		!matches!(self, Self::Queued | Self::Running)
+	}
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct JobStatus {
+	#[serde(flatten)]
+	pub state: JobState,
+
+	// only layouts optimized over epochs report them
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub epoch: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub epochs: Option<u32>,
+	// positions after the last reported epoch, normalized like the final layout
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub partial: Option<Vec<(UuidString, f32, f32)>>,
+}
+
+// shared between a layout computation and whoever waits for it
+pub struct Progress {
+	cancelled: AtomicBool,
+	status: watch::Sender<JobStatus>,
+}
+
+impl Default for Progress {
+	fn default() -> Self {
+		Self {
+			cancelled: AtomicBool::new(false),
+			status: watch::channel(JobStatus::default()).0,
+		}
+	}
+}
+
+impl Progress {
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	fn set_running(&self) {
+		self.status
+			.send_modify(|status| status.state = JobState::Running);
+	}
+
+	// called from the computation to stop it early
+	pub fn check(&self) -> Result<()> {
+		match self.cancelled.load(Ordering::Relaxed) {
+			true => Err(Error::Cancelled),
+			false => Ok(()),
+		}
+	}
+
+	// positions are only collected while someone is watching
+	pub fn epoch<F: FnOnce() -> Vec<(UuidString, f32, f32)>>(
+		&self,
+		epoch: u32,
+		epochs: u32,
+		positions: F,
+	) -> Result<()> {
+		self.check()?;
+
+		let partial = (self.status.receiver_count() > 0
+			&& (epoch.is_multiple_of(PARTIAL_INTERVAL) || epoch + 1 == epochs))
+			.then(|| {
+				let mut positions = positions();
+				normalize_positions(&mut positions);
+				positions
+			});
+		self.status.send_modify(|status| {
+			status.epoch = Some(epoch + 1);
+			status.epochs = Some(epochs);
+			if partial.is_some() {
+				status.partial = partial;
+			}
+		});
+
+		Ok(())
+	}
+
+	fn finish(&self, state: JobState) {
+		self.status.send_modify(|status| {
+			status.state = state;
+			status.partial = None;
+		});
+	}
+
+	// cancels the computation once the returned guard is dropped,
+	// e.g. when the client of a request waiting for it disconnects
+	pub fn cancel_on_drop(self: &Arc<Self>) -> CancelOnDrop {
+		CancelOnDrop(self.clone())
+	}
+}
+
+pub struct CancelOnDrop(Arc<Progress>);
+
+impl Drop for CancelOnDrop {
+	fn drop(&mut self) {
+		self.0.cancel();
+	}
+}
+
+struct Job {
+	collection_id: Uuid,
+	// JSON text of the layout request
+	request: String,
+	progress: Arc<Progress>,
+}
+
+impl Job {
+	// neither finished nor asked to stop, so the same request can wait for it
+	fn is_pending(&self) -> bool {
+		self.progress.check().is_ok() && !self.progress.status.borrow().state.is_finished()
+	}
+}
+
+lazy_static::lazy_static! {
+	// layouts computed in the background, by job id
+	static ref JOBS: std::sync::Mutex<HashMap<Uuid, Job>> = Default::default();
+	static ref RUNNING_JOBS: Semaphore = Semaphore::new(MAX_RUNNING_JOBS);
+}
+
+fn get_job(collection_id: Uuid, job_id: Uuid) -> Result<Arc<Progress>> {
+	JOBS.lock()
+		.unwrap()
+		.get(&job_id)
+		.filter(|job| job.collection_id == collection_id)
+		.map(|job| job.progress.clone())
+		.ok_or(Error::NotFound("layout job".into()))
+}
+
+// runs the computation on its own once fewer than MAX_RUNNING_JOBS are running, returning
+// the job id; a pending job for the same request is reused instead
+pub fn spawn<F, Fut>(collection_id: Uuid, request: String, compute: F) -> Uuid
+where
+	F: FnOnce(Arc<Progress>) -> Fut,
+	Fut: std::future::Future<Output = Result<Uuid>> + Send + 'static,
+{
+	let mut jobs = JOBS.lock().unwrap();
+	let pending = jobs.iter().find(|(_, job)| {
+		job.collection_id == collection_id && job.request == request && job.is_pending()
+	});
+	if let Some((job_id, _)) = pending {
+		return *job_id;
+	}
+
+	let job_id = Uuid::new_v4();
+	let progress = Arc::new(Progress::default());
+	let computation = compute(progress.clone());
+	jobs.insert(
+		job_id,
+		Job {
+			collection_id,
+			request,
+			progress: progress.clone(),
+		},
+	);
+	drop(jobs);
+
+	tokio::spawn(async move {
+		let permit = RUNNING_JOBS.acquire().await;
+		let res = match progress.check() {
+			// cancelled while queued
+			Err(e) => Err(e),
+			Ok(()) => {
+				progress.set_running();
+				// on a task of its own, so a panic fails the job instead of leaving it running
+				tokio::spawn(computation)
+					.await
+					.unwrap_or_else(|e| Err(e.into()))
+			}
+		};
+		drop(permit);
+
+		let state = match res {
+			Ok(layout_id) => JobState::Done {
+				layout_id: UuidString(layout_id),
+			},
+			Err(Error::Cancelled) => JobState::Cancelled,
+			Err(e) => {
+				log::warn!("layout job {} failed: {}", job_id, e);
+				JobState::Failed {
+					error: e.to_string(),
+				}
+			}
+		};
+		progress.finish(state);
+
+		tokio::time::sleep(JOB_RETENTION).await;
+		JOBS.lock().unwrap().remove(&job_id);
+	});
+
+	job_id
+}
+
+// latest status, without partial positions
+pub async fn get_job_status(
+	Path((collection_id, job_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<JobStatus>> {
+	let progress = get_job(collection_id, job_id)?;
+	let status = JobStatus {
+		partial: None,
+		..progress.status.borrow().clone()
+	};
+
+	Ok(Json(status))
+}
+
+// status updates as MessagePack, until the job is finished
+pub async fn watch_job(
+	Path((collection_id, job_id)): Path<(Uuid, Uuid)>,
+	ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse> {
+	let progress = get_job(collection_id, job_id)?;
+
+	Ok(ws.on_upgrade(move |socket| async move {
+		if let Err(e) = send_progress(socket, progress).await {
+			log::debug!("layout job {} progress socket closed: {}", job_id, e);
+		}
+	}))
+}
+
+async fn send_progress(mut socket: WebSocket, progress: Arc<Progress>) -> Result<(), axum::Error> {
+	let mut status = progress.status.subscribe();
+	// updates in between are skipped if the client is slower than the computation
+	loop {
+		let (msg, finished) = {
+			let current = status.borrow_and_update();
+			let finished = current.state.is_finished();
+			(
+				rmp_serde::to_vec_named(&*current).map_err(axum::Error::new)?,
+				finished,
+			)
+		};
+		socket.send(Message::Binary(msg)).await?;
+
+		if finished || status.changed().await.is_err() {
+			break;
+		}
+	}
+
+	socket.close().await
+}
+
+pub async fn cancel_job(Path((collection_id, job_id)): Path<(Uuid, Uuid)>) -> Result<StatusCode> {
+	get_job(collection_id, job_id)?.cancel();
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// final state of the job, waiting for it to finish
+	async fn finished(collection_id: Uuid, job_id: Uuid) -> JobState {
+		let mut status = get_job(collection_id, job_id).unwrap().status.subscribe();
+		let status = status
+			.wait_for(|status| status.state.is_finished())
+			.await
+			.unwrap();
+		status.state.clone()
+	}
+
+	fn failing_computation() -> Result<Uuid> {
+		panic!("layout computation failed");
+	}
+
+	#[tokio::test]
+	async fn panicking_computation_fails_the_job() {
+		let collection_id = Uuid::new_v4();
+		let job_id = spawn(collection_id, "{}".into(), |_| async {
+			failing_computation()
+		});
+		assert!(matches!(
+			finished(collection_id, job_id).await,
+			JobState::Failed { .. }
+		));
+
+		// a new job is started for the same request, as the failed one isn't pending anymore
+		let layout_id = Uuid::new_v4();
+		let retry = spawn(
+			collection_id,
+			"{}".into(),
+			move |_| async move { Ok(layout_id) },
+		);
+		assert_ne!(retry, job_id);
+		match finished(collection_id, retry).await {
+			JobState::Done { layout_id: id } => assert_eq!(id.0, layout_id),
+			state => panic!("unexpected state {:?}", state),
+		}
+	}
+}
@@ -0,0 +1,154 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::err::Result;
+
+use super::jobs::Progress;
+
+// below this many images, neighbours are found by comparing all pairs
+const EXACT_LIMIT: usize = 2048;
+// NN-descent stops once fewer than this fraction of neighbour entries changed in an iteration
+const DESCENT_DELTA: f32 = 0.001;
+const DESCENT_ITERATIONS: usize = 12;
+
+#[derive(Clone, Copy)]
+struct Neighbour {
+	index: usize,
+	dist: f32,
+	// not yet joined with the other neighbours
+	new: bool,
+}
+
+// neighbours sorted by distance, at most k
+struct NeighbourList(Vec<Neighbour>);
+
+impl NeighbourList {
+	fn insert(&mut self, k: usize, index: usize, dist: f32) -> bool {
+		if self.0.len() >= k && self.0.last().is_none_or(|n| dist >= n.dist) {
+			return false;
+		}
+		if self.0.iter().any(|n| n.index == index) {
+			return false;
+		}
+
+		let at = self.0.partition_point(|n| n.dist <= dist);
+		self.0.insert(
+			at,
+			Neighbour {
+				index,
+				dist,
+				new: true,
+			},
+		);
+		self.0.truncate(k);
+		true
+	}
+}
+
+// the k nearest neighbours of every point, closest first, as (index, distance)
+// exact for small inputs, approximated by NN-descent otherwise
+pub fn nearest_neighbours<D: Fn(usize, usize) -> f32>(
+	len: usize,
+	k: usize,
+	dist: D,
+	rng: &mut StdRng,
+	progress: &Progress,
+) -> Result<Vec<Vec<(usize, f32)>>> {
+	let k = k.min(len.saturating_sub(1));
+	let mut lists = (0..len)
+		.map(|_| NeighbourList(Vec::with_capacity(k + 1)))
+		.collect::<Vec<_>>();
+
+	if len <= EXACT_LIMIT {
+		for i in 0..len {
+			progress.check()?;
+			for j in i + 1..len {
+				let d = dist(i, j);
+				lists[i].insert(k, j, d);
+				lists[j].insert(k, i, d);
+			}
+		}
+	} else {
+		nn_descent(&mut lists, k, &dist, rng, progress)?;
+	}
+
+	Ok(lists
+		.into_iter()
+		.map(|list| list.0.into_iter().map(|n| (n.index, n.dist)).collect())
+		.collect())
+}
+
+// neighbours of neighbours are likely neighbours as well; starting from random neighbours,
+// every point's neighbours are compared with each other until the lists settle
+fn nn_descent<D: Fn(usize, usize) -> f32>(
+	lists: &mut [NeighbourList],
+	k: usize,
+	dist: &D,
+	rng: &mut StdRng,
+	progress: &Progress,
+) -> Result<()> {
+	let len = lists.len();
+	for (i, list) in lists.iter_mut().enumerate() {
+		while list.0.len() < k {
+			let j = rng.gen_range(0..len);
+			if j != i {
+				list.insert(k, j, dist(i, j));
+			}
+		}
+	}
+
+	for _ in 0..DESCENT_ITERATIONS {
+		// split neighbours into those that still need joining and those that were joined before,
+		// and add the reverse neighbours of each
+		let mut new = vec![vec![]; len];
+		let mut old = vec![vec![]; len];
+		for (i, list) in lists.iter_mut().enumerate() {
+			for n in list.0.iter_mut() {
+				match n.new {
+					true => {
+						new[i].push(n.index);
+						new[n.index].push(i);
+					}
+					false => {
+						old[i].push(n.index);
+						old[n.index].push(i);
+					}
+				}
+				n.new = false;
+			}
+		}
+
+		// popular points would otherwise take quadratic time
+		for candidates in new.iter_mut().chain(old.iter_mut()) {
+			candidates.sort_unstable();
+			candidates.dedup();
+			if candidates.len() > 2 * k {
+				candidates.shuffle(rng);
+				candidates.truncate(2 * k);
+			}
+		}
+
+		let mut updates = 0;
+		for i in 0..len {
+			progress.check()?;
+			for (a, &u1) in new[i].iter().enumerate() {
+				let pairs = new[i][a + 1..].iter().chain(old[i].iter());
+				for &u2 in pairs {
+					if u1 == u2 {
+						continue;
+					}
+					let d = dist(u1, u2);
+					updates += lists[u1].insert(k, u2, d) as usize;
+					updates += lists[u2].insert(k, u1, d) as usize;
+				}
+			}
+		}
+
+		if (updates as f32) < DESCENT_DELTA * (len * k) as f32 {
+			break;
+		}
+	}
+
+	Ok(())
+}
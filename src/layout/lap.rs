@@ -0,0 +1,333 @@
+use itertools::Itertools;
+
+use crate::err::Result;
+
+use super::jobs::Progress;
+use super::UuidString;
+
+// above this many images, the grid is split into blocks that are assigned separately
+const EXACT_LIMIT: usize = 400;
+
+// minimum cost assignment of rows to distinct columns, there must be at least as many columns as rows
+// Jonker-Volgenant style shortest augmenting paths with dual potentials, O(rows^2 * cols)
+// infinite and NaN costs are allowed, such pairs are only assigned if there is no other way
+pub fn assign(cost: &[Vec<f32>]) -> Vec<usize> {
+	let rows = cost.len();
+	let cols = cost.first().map_or(0, |c| c.len());
+	assert!(rows <= cols, "more rows than columns");
+
+	// replace non-finite costs by one that outweighs any sum of finite ones; with infinite
+	// reduced costs, no free column might ever be reached and the search below wouldn't end
+	let finite_max = cost
+		.iter()
+		.flatten()
+		.filter(|c| c.is_finite())
+		.fold(0f64, |max, c| max.max(c.abs() as f64));
+	let penalty = (finite_max + 1.0) * (rows + 1) as f64;
+	let cost_of = |row: usize, col: usize| match cost[row][col] {
+		c if c.is_finite() => c as f64,
+		f32::NEG_INFINITY => -penalty,
+		_ => penalty,
+	};
+
+	// 1-based, index 0 is the virtual start of each augmenting path
+	let mut u = vec![0f64; rows + 1];
+	let mut v = vec![0f64; cols + 1];
+	let mut row_of = vec![0usize; cols + 1];
+	let mut way = vec![0usize; cols + 1];
+
+	for row in 1..=rows {
+		row_of[0] = row;
+		let mut col0 = 0;
+		let mut min_to = vec![f64::INFINITY; cols + 1];
+		let mut used = vec![false; cols + 1];
+
+		// grow the tree of tight edges until it reaches a free column
+		loop {
+			used[col0] = true;
+			let row0 = row_of[col0];
+			let mut delta = f64::INFINITY;
+			let mut col1 = 0;
+			for col in 1..=cols {
+				if used[col] {
+					continue;
+				}
+				let reduced = cost_of(row0 - 1, col - 1) - u[row0] - v[col];
+				if reduced < min_to[col] {
+					min_to[col] = reduced;
+					way[col] = col0;
+				}
+				if min_to[col] < delta {
+					delta = min_to[col];
+					col1 = col;
+				}
+			}
+
+			for col in 0..=cols {
+				if used[col] {
+					u[row_of[col]] += delta;
+					v[col] -= delta;
+				} else {
+					min_to[col] -= delta;
+				}
+			}
+
+			col0 = col1;
+			if row_of[col0] == 0 {
+				break;
+			}
+		}
+
+		// flip the edges along the augmenting path
+		loop {
+			let col1 = way[col0];
+			row_of[col0] = row_of[col1];
+			col0 = col1;
+			if col0 == 0 {
+				break;
+			}
+		}
+	}
+
+	let mut res = vec![0; rows];
+	for col in 1..=cols {
+		if row_of[col] != 0 {
+			res[row_of[col] - 1] = col - 1;
+		}
+	}
+	res
+}
+
+// assigns points in 0..1 to cells of the block, minimizing the squared displacement
+fn assign_block(
+	positions: &[(UuidString, f32, f32)],
+	points: &[usize],
+	cells: &[(usize, usize)],
+	(rows, cols): (usize, usize),
+	grid: &mut [Vec<Option<UuidString>>],
+	progress: &Progress,
+) -> Result<()> {
+	if points.is_empty() {
+		return Ok(());
+	}
+	progress.check()?;
+
+	if points.len() <= EXACT_LIMIT {
+		let cost = points
+			.iter()
+			.map(|&p| {
+				let (_, x, y) = positions[p];
+				cells
+					.iter()
+					.map(|&(row, col)| {
+						let dx = x - (col as f32 + 0.5) / cols as f32;
+						let dy = y - (row as f32 + 0.5) / rows as f32;
+						dx * dx + dy * dy
+					})
+					.collect_vec()
+			})
+			.collect_vec();
+
+		for (p, cell) in points.iter().zip(assign(&cost)) {
+			let (row, col) = cells[cell];
+			grid[row][col] = Some(positions[*p].0);
+		}
+		return Ok(());
+	}
+
+	// split the block in half along its longer side, and the points the same way,
+	// in proportion to the number of cells on each side
+	let (min_row, max_row) = cells.iter().map(|c| c.0).minmax().into_option().unwrap();
+	let (min_col, max_col) = cells.iter().map(|c| c.1).minmax().into_option().unwrap();
+	let (height, width) = (max_row - min_row + 1, max_col - min_col + 1);
+	let split_rows = height > 1 && (width == 1 || height * cols > width * rows);
+
+	let (first_cells, second_cells): (Vec<_>, Vec<_>) = match split_rows {
+		true => cells.iter().partition(|c| c.0 <= (min_row + max_row) / 2),
+		false => cells.iter().partition(|c| c.1 <= (min_col + max_col) / 2),
+	};
+
+	let mut points = points.to_vec();
+	points.sort_unstable_by(|&a, &b| {
+		let (_, xa, ya) = positions[a];
+		let (_, xb, yb) = positions[b];
+		match split_rows {
+			true => ya.total_cmp(&yb),
+			false => xa.total_cmp(&xb),
+		}
+	});
+
+	let split = ((points.len() * first_cells.len()) as f32 / cells.len() as f32).round() as usize;
+	let split = split
+		.min(first_cells.len())
+		.max(points.len().saturating_sub(second_cells.len()));
+
+	assign_block(
+		positions,
+		&points[..split],
+		&first_cells,
+		(rows, cols),
+		grid,
+		progress,
+	)?;
+	assign_block(
+		positions,
+		&points[split..],
+		&second_cells,
+		(rows, cols),
+		grid,
+		progress,
+	)
+}
+
+// rasterizes positions in 0..1 into a near-square grid with one image per cell, rows first
+// small layouts are assigned exactly, large ones in blocks that are split recursively
+pub fn assign_to_grid(
+	positions: &[(UuidString, f32, f32)],
+	progress: &Progress,
+) -> Result<Vec<Vec<Option<UuidString>>>> {
+	let cols = (positions.len() as f32).sqrt().ceil().max(1.0) as usize;
+	let rows = positions.len().div_ceil(cols);
+
+	let cells = (0..rows).cartesian_product(0..cols).collect_vec();
+	let points = (0..positions.len()).collect_vec();
+
+	let mut grid = vec![vec![None; cols]; rows];
+	assign_block(
+		positions,
+		&points,
+		&cells,
+		(rows, cols),
+		&mut grid,
+		progress,
+	)?;
+	Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use rand::rngs::StdRng;
+	use rand::{Rng, SeedableRng};
+	use uuid::Uuid;
+
+	use super::*;
+
+	fn total(cost: &[Vec<f32>], assignment: &[usize]) -> f32 {
+		assignment
+			.iter()
+			.enumerate()
+			.map(|(row, &col)| cost[row][col])
+			.sum()
+	}
+
+	#[test]
+	fn assign_known_cases() {
+		assert_eq!(assign(&[]), Vec::<usize>::new());
+		let cost = vec![
+			vec![4.0, 1.0, 3.0],
+			vec![2.0, 0.0, 5.0],
+			vec![3.0, 2.0, 2.0],
+		];
+		assert_eq!(assign(&cost), vec![1, 0, 2]);
+		// more columns than rows
+		let cost = vec![vec![5.0, 1.0, 9.0, 9.0], vec![5.0, 1.0, 9.0, 0.0]];
+		assert_eq!(assign(&cost), vec![1, 3]);
+	}
+
+	#[test]
+	fn assign_matches_brute_force() {
+		let mut rng = StdRng::seed_from_u64(0);
+		for _ in 0..20 {
+			let (rows, cols) = (rng.gen_range(1..6), 6);
+			let cost = (0..rows)
+				.map(|_| (0..cols).map(|_| rng.gen_range(0.0..1.0f32)).collect_vec())
+				.collect_vec();
+
+			let best = (0..cols)
+				.permutations(rows)
+				.map(|p| total(&cost, &p))
+				.fold(f32::INFINITY, f32::min);
+			let res = assign(&cost);
+			assert_eq!(res.iter().collect::<HashSet<_>>().len(), rows);
+			assert!((total(&cost, &res) - best).abs() < 1e-5);
+		}
+	}
+
+	#[test]
+	fn assign_non_finite_costs() {
+		let (inf, nan) = (f32::INFINITY, f32::NAN);
+		let mut res = assign(&[vec![inf, nan], vec![nan, inf]]);
+		res.sort();
+		assert_eq!(res, vec![0, 1]);
+		assert_eq!(assign(&[vec![1.0, nan], vec![nan, 1.0]]), vec![0, 1]);
+		assert_eq!(assign(&[vec![nan, 0.0], vec![0.0, inf]]), vec![1, 0]);
+		assert_eq!(
+			assign(&[vec![0.0, f32::NEG_INFINITY], vec![1.0, 0.0]]),
+			vec![1, 0]
+		);
+	}
+
+	fn positions(points: &[(f32, f32)]) -> Vec<(UuidString, f32, f32)> {
+		points
+			.iter()
+			.enumerate()
+			.map(|(i, &(x, y))| (UuidString(Uuid::from_u128(i as u128 + 1)), x, y))
+			.collect()
+	}
+
+	// points at the centres of a side x side grid, in reverse order
+	fn lattice(side: usize) -> Vec<(f32, f32)> {
+		(0..side * side)
+			.rev()
+			.map(|i| {
+				let (row, col) = (i / side, i % side);
+				(
+					(col as f32 + 0.5) / side as f32,
+					(row as f32 + 0.5) / side as f32,
+				)
+			})
+			.collect()
+	}
+
+	fn assert_on_own_cells(side: usize) {
+		let points = positions(&lattice(side));
+		let grid = assign_to_grid(&points, &Progress::default()).unwrap();
+		assert_eq!((grid.len(), grid[0].len()), (side, side));
+		for (id, x, y) in &points {
+			let (row, col) = ((y * side as f32) as usize, (x * side as f32) as usize);
+			assert_eq!(grid[row][col].map(|id| id.0), Some(id.0));
+		}
+	}
+
+	#[test]
+	fn assign_to_grid_keeps_points_on_their_cells() {
+		// exactly, and split into blocks
+		assert_on_own_cells(3);
+		assert_on_own_cells(30);
+	}
+
+	#[test]
+	fn assign_to_grid_places_every_point_once() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let points = (0..1000)
+			.map(|_| (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))
+			.collect_vec();
+		let points = positions(&points);
+		let grid = assign_to_grid(&points, &Progress::default()).unwrap();
+
+		// 32 columns and as many rows as needed for the rest
+		assert_eq!((grid.len(), grid[0].len()), (32, 32));
+		let placed = grid.iter().flatten().flatten().map(|id| id.0).collect_vec();
+		assert_eq!(placed.len(), points.len());
+		assert_eq!(placed.iter().collect::<HashSet<_>>().len(), points.len());
+	}
+
+	#[test]
+	fn cancelled() {
+		let progress = Progress::default();
+		progress.cancel();
+		assert!(assign_to_grid(&positions(&lattice(3)), &progress).is_err());
+	}
+}
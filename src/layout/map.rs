@@ -0,0 +1,116 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::annotations::{Annotations, Axis};
+use super::jobs::Progress;
+use super::snap::snap_to_grid;
+use super::UuidString;
+
+// Web Mercator is undefined at the poles, and cut off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+type Positions = Vec<(UuidString, f32, f32)>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct MapOptions {
+	// zoom into the area covered by images; otherwise positions cover the whole world
+	pub fit: Option<bool>,
+	// number of grid cells along each axis to snap positions to, so nearby images don't overlap
+	// at most 4096
+	pub snap: Option<u32>,
+}
+
+// Web Mercator in 0..1, north-west is (0, 0)
+pub fn project(latitude: f64, longitude: f64) -> (f64, f64) {
+	let lat = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+	let x = (longitude + 180.0) / 360.0;
+	let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0;
+	(x, y)
+}
+
+// inverse of the projection, in degrees
+fn unproject(x: f64, y: f64) -> (f64, f64) {
+	let longitude = x * 360.0 - 180.0;
+	let latitude = (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees();
+	(latitude, longitude)
+}
+
+// images without a GPS position are left out; the axes hold the longitude and latitude
+// at the edges, so a map can be drawn underneath
+pub fn map(
+	metadata: &[Image],
+	opts: MapOptions,
+	progress: &Progress,
+) -> Result<(Positions, Annotations)> {
+	let projected = metadata
+		.iter()
+		.filter_map(|img| {
+			img.metadata
+				.gps
+				.map(|gps| (img.id, project(gps.latitude, gps.longitude)))
+		})
+		.collect::<Vec<_>>();
+
+	// scale both axes by the same factor to keep the map's aspect ratio, centre the covered area
+	let (offset_x, offset_y, scale) = match opts.fit.unwrap_or(true) {
+		true if !projected.is_empty() => {
+			let (min_x, min_y, max_x, max_y) = projected.iter().fold(
+				(f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+				|(min_x, min_y, max_x, max_y), (_, (x, y))| {
+					(min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+				},
+			);
+
+			let (width, height) = (max_x - min_x, max_y - min_y);
+			let span = width.max(height);
+			match span > 0.0 {
+				true => (
+					min_x - (span - width) / 2.0,
+					min_y - (span - height) / 2.0,
+					span.recip(),
+				),
+				// all images at the same spot end up in the middle
+				false => (min_x - 0.5, min_y - 0.5, 1.0),
+			}
+		}
+		_ => (0.0, 0.0, 1.0),
+	};
+
+	let mut res = projected
+		.into_iter()
+		.map(|(id, (x, y))| {
+			(
+				UuidString(id),
+				((x - offset_x) * scale) as f32,
+				((y - offset_y) * scale) as f32,
+			)
+		})
+		.collect::<Vec<_>>();
+
+	if let Some(cells) = opts.snap {
+		snap_to_grid(&mut res, cells, progress)?;
+	}
+
+	let (north, west) = unproject(offset_x, offset_y);
+	let (south, east) = unproject(offset_x + scale.recip(), offset_y + scale.recip());
+	let annotations = Annotations {
+		x_axis: Some(Axis {
+			label: "longitude".into(),
+			start: west,
+			end: east,
+		}),
+		y_axis: Some(Axis {
+			label: "latitude".into(),
+			start: north,
+			end: south,
+		}),
+		..Default::default()
+	};
+
+	Ok((res, annotations))
+}
@@ -0,0 +1,205 @@
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::dist::{DistanceFunction, DistanceFunctionVariants};
+use super::jobs::Progress;
+use super::{normalize_positions, UuidString};
+
+const DEFAULT_LANDMARKS: usize = 256;
+const POWER_ITERATIONS: usize = 200;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PcaOptions {
+	pub dist: DistanceFunctionVariants,
+	// number of images the projection is computed from; more are more accurate, but slower
+	pub landmarks: Option<usize>,
+	pub seed: Option<u64>,
+}
+
+// unit eigenvector with the largest eigenvalue of a symmetric matrix, orthogonal to `exclude`
+fn dominant_eigenvector(
+	matrix: &[Vec<f64>],
+	exclude: &[Vec<f64>],
+	rng: &mut StdRng,
+	progress: &Progress,
+) -> Result<(Vec<f64>, f64)> {
+	let len = matrix.len();
+	let mut v = (0..len)
+		.map(|_| rng.gen_range(-1.0..1.0))
+		.collect::<Vec<f64>>();
+	let mut eigenvalue = 0.0;
+
+	for _ in 0..POWER_ITERATIONS {
+		progress.check()?;
+
+		for e in exclude {
+			let dot = v.iter().zip(e).map(|(a, b)| a * b).sum::<f64>();
+			v.iter_mut().zip(e).for_each(|(a, b)| *a -= dot * b);
+		}
+
+		let next = matrix
+			.iter()
+			.map(|row| row.iter().zip(&v).map(|(a, b)| a * b).sum::<f64>())
+			.collect::<Vec<_>>();
+		let norm = next.iter().map(|a| a * a).sum::<f64>().sqrt();
+		if norm == 0.0 {
+			break;
+		}
+
+		eigenvalue = next.iter().zip(&v).map(|(a, b)| a * b).sum::<f64>();
+		v = next.into_iter().map(|a| a / norm).collect();
+	}
+
+	Ok((v, eigenvalue))
+}
+
+// landmark MDS: classical MDS, i.e. PCA on distances, of a random sample of images,
+// every other image is placed by triangulation from its distances to the landmarks
+// linear in the number of images, and results are stable across runs with the same seed
+pub fn landmark_mds<D: DistanceFunction>(
+	dist: &D,
+	metadata: &[Image],
+	landmarks: usize,
+	seed: u64,
+	progress: &Progress,
+) -> Result<Vec<(UuidString, f32, f32)>> {
+	if metadata.is_empty() {
+		return Ok(vec![]);
+	}
+
+	let mut rng = StdRng::seed_from_u64(seed);
+	let landmarks = sample(&mut rng, metadata.len(), landmarks.clamp(1, metadata.len())).into_vec();
+
+	// squared distances to the landmarks; missing metadata gets the largest distance there is
+	let mut squared = metadata
+		.iter()
+		.map(|img| {
+			progress.check()?;
+			Ok(landmarks
+				.iter()
+				.map(|&l| (dist.dist(img, &metadata[l]).abs() as f64).powi(2))
+				.collect::<Vec<_>>())
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let max = squared
+		.iter()
+		.flatten()
+		.filter(|d| d.is_finite())
+		.fold(0.0, |max: f64, d| max.max(*d));
+	squared
+		.iter_mut()
+		.flatten()
+		.filter(|d| !d.is_finite())
+		.for_each(|d| *d = max);
+
+	// double centring of the squared distances between landmarks
+	let k = landmarks.len();
+	let between = landmarks.iter().map(|&l| &squared[l]).collect::<Vec<_>>();
+	let column_means = (0..k)
+		.map(|j| between.iter().map(|row| row[j]).sum::<f64>() / k as f64)
+		.collect::<Vec<_>>();
+	let mean = column_means.iter().sum::<f64>() / k as f64;
+	let centred = (0..k)
+		.map(|i| {
+			(0..k)
+				.map(|j| -0.5 * (between[i][j] - column_means[i] - column_means[j] + mean))
+				.collect::<Vec<_>>()
+		})
+		.collect::<Vec<_>>();
+
+	let (v1, l1) = dominant_eigenvector(&centred, &[], &mut rng, progress)?;
+	let (v2, l2) = dominant_eigenvector(&centred, std::slice::from_ref(&v1), &mut rng, progress)?;
+
+	// pseudo-inverse of the landmark coordinates
+	let axis = |v: &[f64], l: f64| match l > 0.0 {
+		true => v.iter().map(|a| a / l.sqrt()).collect::<Vec<_>>(),
+		false => vec![0.0; v.len()],
+	};
+	let (a1, a2) = (axis(&v1, l1), axis(&v2, l2));
+
+	let mut res = metadata
+		.iter()
+		.zip(squared)
+		.map(|(img, squared)| {
+			let coordinate = |axis: &[f64]| {
+				-0.5 * axis
+					.iter()
+					.zip(squared.iter().zip(&column_means))
+					.map(|(a, (d, m))| a * (d - m))
+					.sum::<f64>()
+			};
+			(
+				UuidString(img.id),
+				coordinate(&a1) as f32,
+				coordinate(&a2) as f32,
+			)
+		})
+		.collect::<Vec<_>>();
+
+	normalize_positions(&mut res);
+	Ok(res)
+}
+
+pub fn pca<D: DistanceFunction>(
+	dist: D,
+	metadata: &[Image],
+	opts: PcaOptions,
+	progress: &Progress,
+) -> Result<Vec<(UuidString, f32, f32)>> {
+	landmark_mds(
+		&dist,
+		metadata,
+		opts.landmarks.unwrap_or(DEFAULT_LANDMARKS),
+		opts.seed.unwrap_or_default(),
+		progress,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::testing::{coordinates, images_at};
+	use super::*;
+
+	#[test]
+	fn recovers_known_configuration() {
+		// a grid wider than high, so the principal axes are the x and y axis
+		let points = (0..8)
+			.flat_map(|x| (0..4).map(move |y| (x as f32, y as f32)))
+			.collect::<Vec<_>>();
+		let (images, dist) = images_at(&points);
+		let layout = landmark_mds(&dist, &images, points.len(), 3, &Progress::default()).unwrap();
+		let layout = coordinates(&layout);
+
+		// positions are normalized, and either axis may be mirrored
+		let expected = points
+			.iter()
+			.map(|(x, y)| (x / 7.0, y / 3.0))
+			.collect::<Vec<_>>();
+		let matches = |flip_x: bool, flip_y: bool| {
+			layout.iter().zip(&expected).all(|((x, y), (ex, ey))| {
+				let ex = if flip_x { 1.0 - ex } else { *ex };
+				let ey = if flip_y { 1.0 - ey } else { *ey };
+				(x - ex).abs() < 1e-3 && (y - ey).abs() < 1e-3
+			})
+		};
+		assert!([(false, false), (true, false), (false, true), (true, true)]
+			.into_iter()
+			.any(|(flip_x, flip_y)| matches(flip_x, flip_y)));
+	}
+
+	#[test]
+	fn same_seed_gives_same_layout() {
+		let points = (0..50)
+			.map(|i| ((i * 7 % 13) as f32, (i * 5 % 11) as f32))
+			.collect::<Vec<_>>();
+		let (images, dist) = images_at(&points);
+		let layout = || landmark_mds(&dist, &images, 10, 5, &Progress::default()).unwrap();
+		assert_eq!(coordinates(&layout()), coordinates(&layout()));
+	}
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::err::Result;
+
+use super::jobs::Progress;
+use super::UuidString;
+
+// requested grid sizes are capped at this many cells along each axis
+const MAX_CELLS: u32 = 4096;
+
+// cells at Chebyshev distance r around a cell, walking only the perimeter of the ring
+fn ring(cx: i32, cy: i32, r: i32) -> impl Iterator<Item = (i32, i32)> {
+	let rows = (cx - r..=cx + r).flat_map(move |x| [(x, cy - r), (x, cy + r)]);
+	let cols = (cy - r + 1..cy + r).flat_map(move |y| [(cx - r, y), (cx + r, y)]);
+	// the single cell of ring 0 is returned twice otherwise
+	rows.chain(cols).dedup()
+}
+
+// move positions in 0..1 to the centres of distinct cells of a grid, so images don't overlap
+// every position takes the free cell closest to it; the grid grows if there are too few cells
+pub fn snap_to_grid(
+	positions: &mut [(UuidString, f32, f32)],
+	cells: u32,
+	progress: &Progress,
+) -> Result<()> {
+	let min_cells = (positions.len() as f32).sqrt().ceil() as u32;
+	let cells = cells.min(MAX_CELLS).max(min_cells).max(1) as i32;
+	let mut taken = vec![false; (cells * cells) as usize];
+
+	let cell_of = |v: f32| ((v.clamp(0.0, 1.0) * cells as f32) as i32).min(cells - 1);
+	let centre_of = |c: i32| (c as f32 + 0.5) / cells as f32;
+
+	// positions in dense areas are placed first, so outliers don't push them around
+	let mut density = HashMap::<(i32, i32), u32>::new();
+	for (_, x, y) in positions.iter() {
+		*density.entry((cell_of(*x), cell_of(*y))).or_default() += 1;
+	}
+	let order = (0..positions.len())
+		.sorted_by_key(|&i| {
+			let (_, x, y) = positions[i];
+			std::cmp::Reverse(density[&(cell_of(x), cell_of(y))])
+		})
+		.collect_vec();
+
+	// taken cells are never freed, so rings that are full around a cell stay full
+	// and later searches from the same cell start beyond them
+	let mut first_open = HashMap::<(i32, i32), i32>::new();
+	for i in order {
+		progress.check()?;
+
+		let (_, x, y) = positions[i];
+		let (cx, cy) = (cell_of(x), cell_of(y));
+		let start = first_open.entry((cx, cy)).or_default();
+
+		// search rings of growing distance around the cell for the closest free one; a cell
+		// on the next ring can still be closer, as the position isn't at the centre of its cell
+		let mut best: Option<(f32, i32, i32)> = None;
+		for r in *start..cells {
+			let min_d = ((r as f32 - 0.5).max(0.0) / cells as f32).powi(2);
+			if best.is_some_and(|(best_d, _, _)| best_d <= min_d) {
+				break;
+			}
+
+			let mut open = false;
+			for (gx, gy) in ring(cx, cy, r) {
+				if gx < 0 || gy < 0 || gx >= cells || gy >= cells {
+					continue;
+				}
+				if taken[(gy * cells + gx) as usize] {
+					continue;
+				}
+				open = true;
+
+				let d = (centre_of(gx) - x).powi(2) + (centre_of(gy) - y).powi(2);
+				if best.is_none_or(|(best_d, _, _)| d < best_d) {
+					best = Some((d, gx, gy));
+				}
+			}
+
+			if !open && r == *start {
+				*start = r + 1;
+			}
+		}
+
+		// there are at least as many cells as positions, so a free one is always found
+		let (_, gx, gy) = best.unwrap();
+		taken[(gy * cells + gx) as usize] = true;
+		positions[i].1 = centre_of(gx);
+		positions[i].2 = centre_of(gy);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use rand::rngs::StdRng;
+	use rand::{Rng, SeedableRng};
+	use uuid::Uuid;
+
+	use super::*;
+
+	fn positions(points: impl Iterator<Item = (f32, f32)>) -> Vec<(UuidString, f32, f32)> {
+		points
+			.map(|(x, y)| (UuidString(Uuid::new_v4()), x, y))
+			.collect()
+	}
+
+	// grid cells of the snapped positions, which must all be cell centres
+	fn snapped_cells(positions: &[(UuidString, f32, f32)], cells: u32) -> Vec<(u32, u32)> {
+		positions
+			.iter()
+			.map(|(_, x, y)| {
+				let (gx, gy) = (x * cells as f32 - 0.5, y * cells as f32 - 0.5);
+				assert!((gx - gx.round()).abs() < 1e-2 && (gy - gy.round()).abs() < 1e-2);
+				(gx.round() as u32, gy.round() as u32)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn positions_in_free_cells_keep_them() {
+		let mut res = positions([(0.05, 0.05), (0.55, 0.15), (0.95, 0.95)].into_iter());
+		snap_to_grid(&mut res, 10, &Progress::default()).unwrap();
+		assert_eq!(snapped_cells(&res, 10), vec![(0, 0), (5, 1), (9, 9)]);
+	}
+
+	#[test]
+	fn colliding_positions_take_distinct_neighbouring_cells() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut res =
+			positions((0..500).map(|_| (rng.gen_range(0.4..0.6f32), rng.gen_range(0.4..0.6f32))));
+		snap_to_grid(&mut res, 40, &Progress::default()).unwrap();
+
+		let cells = snapped_cells(&res, 40);
+		assert_eq!(cells.iter().collect::<HashSet<_>>().len(), res.len());
+		// 500 cells are a square of 23 cells around the centre, give or take a few
+		assert!(cells
+			.iter()
+			.all(|(x, y)| (4..36).contains(x) && (4..36).contains(y)));
+	}
+
+	#[test]
+	fn coincident_positions_fill_a_disc() {
+		let count = 5000;
+		let mut res = positions((0..count).map(|_| (0.5, 0.5)));
+		snap_to_grid(&mut res, MAX_CELLS, &Progress::default()).unwrap();
+
+		let cells = snapped_cells(&res, MAX_CELLS);
+		assert_eq!(cells.iter().collect::<HashSet<_>>().len(), count);
+		let radius = (count as f32 / std::f32::consts::PI).sqrt() + 2.0;
+		let centre = MAX_CELLS as f32 / 2.0;
+		assert!(cells.iter().all(|(x, y)| {
+			(*x as f32 + 0.5 - centre).hypot(*y as f32 + 0.5 - centre) <= radius
+		}));
+	}
+
+	#[test]
+	fn cancelled() {
+		let progress = Progress::default();
+		progress.cancel();
+		let mut res = positions((0..10).map(|_| (0.5, 0.5)));
+		assert!(snap_to_grid(&mut res, 10, &progress).is_err());
+	}
+}
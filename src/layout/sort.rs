@@ -24,14 +24,27 @@ pub trait CompareFunction {
 	fn compare(&self, m1: &Image, m2: &Image) -> Ordering;
 }
 
+impl<C: CompareFunction> CompareFunction for &C {
+	#[inline(always)]
+	fn compare(&self, m1: &Image, m2: &Image) -> Ordering {
+		(**self).compare(m1, m2)
+	}
+}
+
 pub struct SignedDist<D: DistanceFunction> {
 	pub dist: D,
 }
 
+// by key, images without one go last
 impl<D: DistanceFunction> CompareFunction for SignedDist<D> {
 	#[inline(always)]
 	fn compare(&self, m1: &Image, m2: &Image) -> Ordering {
-		self.dist.dist(m1, m2).partial_cmp(&0.0).unwrap()
+		match (self.dist.key(m1), self.dist.key(m2)) {
+			(Some(k1), Some(k2)) => k1.total_cmp(&k2),
+			(Some(_), None) => Ordering::Less,
+			(None, Some(_)) => Ordering::Greater,
+			(None, None) => Ordering::Equal,
+		}
 	}
 }
 
@@ -44,7 +57,6 @@ impl<D: DistanceFunction> CompareFunction for CompareDist<D> {
 	fn compare(&self, m1: &Image, m2: &Image) -> Ordering {
 		self.dist
 			.dist(&self.compared_to, m1)
-			.partial_cmp(&self.dist.dist(&self.compared_to, m2))
-			.unwrap()
+			.total_cmp(&self.dist.dist(&self.compared_to, m2))
 	}
 }
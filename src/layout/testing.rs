@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use uuid::Uuid;
+
+use crate::db::Image;
+
+use super::dist::DistanceFunction;
+use super::UuidString;
+
+// images placed at known points, with the euclidean distance between them
+pub struct PointDist(HashMap<Uuid, (f32, f32)>);
+
+impl DistanceFunction for PointDist {
+	fn dist(&self, m1: &Image, m2: &Image) -> f32 {
+		let (p1, p2) = (self.0[&m1.id], self.0[&m2.id]);
+		((p1.0 - p2.0).powi(2) + (p1.1 - p2.1).powi(2)).sqrt()
+	}
+}
+
+pub fn images_at(points: &[(f32, f32)]) -> (Vec<Image>, PointDist) {
+	let images = (0..points.len())
+		.map(|i| Image {
+			id: Uuid::from_u128(i as u128 + 1),
+			collection_id: Uuid::nil(),
+			width: 1,
+			height: 1,
+			metadata: sqlx::types::Json(Default::default()),
+		})
+		.collect::<Vec<_>>();
+	let dist = PointDist(
+		images
+			.iter()
+			.map(|img| img.id)
+			.zip(points.iter().copied())
+			.collect(),
+	);
+	(images, dist)
+}
+
+// points scattered uniformly within the radius around each centre, cluster by cluster
+pub fn clusters(centres: &[(f32, f32)], per_cluster: usize, radius: f32) -> Vec<(f32, f32)> {
+	let mut rng = StdRng::seed_from_u64(0);
+	centres
+		.iter()
+		.flat_map(|c| {
+			(0..per_cluster)
+				.map(|_| {
+					(
+						c.0 + rng.gen_range(-radius..radius),
+						c.1 + rng.gen_range(-radius..radius),
+					)
+				})
+				.collect::<Vec<_>>()
+		})
+		.collect()
+}
+
+// whether every point in the layout is closer to the centroid of its own cluster than to any other
+pub fn clusters_separated(layout: &[(UuidString, f32, f32)], per_cluster: usize) -> bool {
+	let centroids = layout
+		.chunks(per_cluster)
+		.map(|cluster| {
+			let len = cluster.len() as f32;
+			(
+				cluster.iter().map(|(_, x, _)| x).sum::<f32>() / len,
+				cluster.iter().map(|(_, _, y)| y).sum::<f32>() / len,
+			)
+		})
+		.collect::<Vec<_>>();
+
+	layout.iter().enumerate().all(|(i, (_, x, y))| {
+		let d = |c: &(f32, f32)| (c.0 - x).powi(2) + (c.1 - y).powi(2);
+		let own = d(&centroids[i / per_cluster]);
+		centroids
+			.iter()
+			.enumerate()
+			.all(|(j, c)| j == i / per_cluster || d(c) > own)
+	})
+}
+
+pub fn coordinates(layout: &[(UuidString, f32, f32)]) -> Vec<(f32, f32)> {
+	layout.iter().map(|(_, x, y)| (*x, *y)).collect()
+}
@@ -0,0 +1,352 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::dist::{DistanceFunction, DistanceFunctionVariants};
+use super::jobs::Progress;
+use super::knn::nearest_neighbours;
+use super::{normalize_positions, positions, UuidString};
+
+const DEFAULT_PERPLEXITY: f32 = 30.0;
+const DEFAULT_EPOCHS: u32 = 1000;
+const DEFAULT_THETA: f32 = 0.5;
+
+// the first epochs exaggerate attraction, so clusters form before they are spread out
+const EXAGGERATION: f32 = 12.0;
+const EXAGGERATION_EPOCHS: u32 = 250;
+const MOMENTUM: f32 = 0.5;
+const FINAL_MOMENTUM: f32 = 0.8;
+const LEARNING_RATE: f32 = 200.0;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TsneOptions {
+	pub dist: DistanceFunctionVariants,
+	// roughly the number of neighbours each image is attracted by
+	pub perplexity: Option<f32>,
+	pub epochs: Option<u32>,
+	// accuracy of the Barnes-Hut approximation; 0 is exact, larger values are faster
+	pub theta: Option<f32>,
+	pub seed: Option<u64>,
+}
+
+// conditional probabilities of neighbours, with the bandwidth found by binary search
+// so the entropy matches the perplexity
+fn affinities(neighbours: &[(usize, f32)], perplexity: f32) -> Vec<f32> {
+	let target = perplexity.max(1.0).ln();
+	let (mut beta, mut min_beta, mut max_beta) = (1.0f32, 0.0f32, f32::INFINITY);
+	let squared = neighbours
+		.iter()
+		.map(|(_, d)| match d.is_finite() {
+			true => d * d,
+			false => f32::MAX,
+		})
+		.collect::<Vec<_>>();
+	// shifting distances doesn't change the probabilities, but keeps exp() from underflowing
+	let min = squared.iter().copied().fold(f32::INFINITY, f32::min);
+	let mut p = vec![0.0; neighbours.len()];
+
+	for _ in 0..200 {
+		p.iter_mut()
+			.zip(&squared)
+			.for_each(|(p, d)| *p = (-beta * (d - min)).exp());
+		let sum = p.iter().sum::<f32>().max(f32::MIN_POSITIVE);
+		let entropy = beta
+			* p.iter()
+				.zip(&squared)
+				.map(|(p, d)| p * (d - min))
+				.sum::<f32>()
+			/ sum + sum.ln();
+		p.iter_mut().for_each(|p| *p /= sum);
+
+		if (entropy - target).abs() < 1e-5 {
+			break;
+		}
+		if entropy > target {
+			min_beta = beta;
+			beta = match max_beta.is_finite() {
+				true => (beta + max_beta) / 2.0,
+				false => beta * 2.0,
+			};
+		} else {
+			max_beta = beta;
+			beta = (beta + min_beta) / 2.0;
+		}
+	}
+
+	p
+}
+
+// Barnes-Hut quadtree, summarizing far away points by their centre of mass
+struct QuadTree {
+	nodes: Vec<QuadNode>,
+}
+
+struct QuadNode {
+	centre: (f32, f32),
+	half_width: f32,
+	mass: f32,
+	centre_of_mass: (f32, f32),
+	// index of the first of four children, or the point held by a leaf
+	children: Option<usize>,
+	point: Option<usize>,
+}
+
+impl QuadTree {
+	fn new(points: &[(f32, f32)]) -> Self {
+		let (min_x, min_y, max_x, max_y) = points.iter().fold(
+			(f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+			|(min_x, min_y, max_x, max_y), (x, y)| {
+				(min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+			},
+		);
+		let mut tree = Self {
+			nodes: vec![QuadNode::new(
+				((min_x + max_x) / 2.0, (min_y + max_y) / 2.0),
+				((max_x - min_x).max(max_y - min_y) / 2.0).max(1e-5) * 1.001,
+			)],
+		};
+		for (i, p) in points.iter().enumerate() {
+			tree.insert(0, i, *p, points, 0);
+		}
+		tree
+	}
+
+	fn insert(&mut self, node: usize, i: usize, p: (f32, f32), points: &[(f32, f32)], depth: u32) {
+		{
+			let n = &mut self.nodes[node];
+			n.centre_of_mass = (
+				(n.centre_of_mass.0 * n.mass + p.0) / (n.mass + 1.0),
+				(n.centre_of_mass.1 * n.mass + p.1) / (n.mass + 1.0),
+			);
+			n.mass += 1.0;
+		}
+
+		// duplicate points would be split forever
+		if depth > 48 {
+			return;
+		}
+
+		match (self.nodes[node].children, self.nodes[node].point) {
+			(None, None) if self.nodes[node].mass == 1.0 => self.nodes[node].point = Some(i),
+			(None, point) => {
+				let first = self.nodes.len();
+				let (centre, half_width) =
+					(self.nodes[node].centre, self.nodes[node].half_width / 2.0);
+				for (dx, dy) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+					self.nodes.push(QuadNode::new(
+						(centre.0 + dx * half_width, centre.1 + dy * half_width),
+						half_width,
+					));
+				}
+				self.nodes[node].children = Some(first);
+				self.nodes[node].point = None;
+				if let Some(point) = point {
+					let child = self.child_for(node, points[point]);
+					self.insert(child, point, points[point], points, depth + 1);
+				}
+				let child = self.child_for(node, p);
+				self.insert(child, i, p, points, depth + 1);
+			}
+			(Some(_), _) => {
+				let child = self.child_for(node, p);
+				self.insert(child, i, p, points, depth + 1);
+			}
+		}
+	}
+
+	fn child_for(&self, node: usize, p: (f32, f32)) -> usize {
+		let n = &self.nodes[node];
+		let first = n.children.unwrap();
+		first + (p.0 >= n.centre.0) as usize + 2 * (p.1 >= n.centre.1) as usize
+	}
+
+	// repulsive force on a point, and its contribution to the normalization
+	fn repulsion(&self, p: (f32, f32), theta: f32) -> ((f32, f32), f32) {
+		let (mut force, mut sum_q) = ((0.0, 0.0), 0.0);
+		let mut stack = vec![0];
+		while let Some(node) = stack.pop() {
+			let n = &self.nodes[node];
+			if n.mass == 0.0 {
+				continue;
+			}
+
+			let (dx, dy) = (p.0 - n.centre_of_mass.0, p.1 - n.centre_of_mass.1);
+			let d2 = dx * dx + dy * dy;
+			match n.children {
+				Some(first) if 2.0 * n.half_width >= theta * d2.sqrt() => {
+					stack.extend(first..first + 4);
+				}
+				// the point itself
+				_ if d2 < 1e-12 && n.children.is_none() => {}
+				_ => {
+					let q = 1.0 / (1.0 + d2);
+					sum_q += n.mass * q;
+					force.0 += n.mass * q * q * dx;
+					force.1 += n.mass * q * q * dy;
+				}
+			}
+		}
+		(force, sum_q)
+	}
+}
+
+impl QuadNode {
+	fn new(centre: (f32, f32), half_width: f32) -> Self {
+		Self {
+			centre,
+			half_width,
+			mass: 0.0,
+			centre_of_mass: (0.0, 0.0),
+			children: None,
+			point: None,
+		}
+	}
+}
+
+// Barnes-Hut t-SNE over the nearest neighbours, seeded so it gives the same result every time
+pub fn tsne<D: DistanceFunction>(
+	dist: D,
+	metadata: &[Image],
+	opts: TsneOptions,
+	progress: &Progress,
+) -> Result<Vec<(UuidString, f32, f32)>> {
+	let len = metadata.len();
+	let perplexity = opts.perplexity.unwrap_or(DEFAULT_PERPLEXITY);
+	let theta = opts.theta.unwrap_or(DEFAULT_THETA);
+	let mut rng = StdRng::seed_from_u64(opts.seed.unwrap_or_default());
+
+	let neighbours = nearest_neighbours(
+		len,
+		(3.0 * perplexity) as usize,
+		|i, j| dist.dist(&metadata[i], &metadata[j]).abs(),
+		&mut rng,
+		progress,
+	)?;
+
+	// symmetric joint probabilities, as a list of edges
+	let mut edges = vec![];
+	for (i, n) in neighbours.iter().enumerate() {
+		progress.check()?;
+		let p = affinities(n, perplexity);
+		edges.extend(n.iter().zip(p).map(|((j, _), p)| (i.min(*j), i.max(*j), p)));
+	}
+	edges.sort_unstable_by_key(|(i, j, _)| (*i, *j));
+	let mut joint: Vec<(usize, usize, f32)> = vec![];
+	for (i, j, p) in edges {
+		match joint.last_mut() {
+			Some(last) if last.0 == i && last.1 == j => last.2 += p,
+			_ => joint.push((i, j, p)),
+		}
+	}
+	let sum = joint
+		.iter()
+		.map(|(_, _, p)| p)
+		.sum::<f32>()
+		.max(f32::MIN_POSITIVE);
+	joint.iter_mut().for_each(|(_, _, p)| *p /= sum);
+
+	// small random start, as in the original algorithm
+	let mut y = (0..len)
+		.map(|_| {
+			// Box-Muller
+			let (u, v) = (rng.gen_range(f32::EPSILON..1.0), rng.gen_range(0.0..1.0));
+			let r = 1e-4 * (-2.0 * u.ln()).sqrt();
+			let angle = 2.0 * std::f32::consts::PI * v;
+			(r * angle.cos(), r * angle.sin())
+		})
+		.collect::<Vec<_>>();
+	let mut velocity = vec![(0.0, 0.0); len];
+	let mut gains = vec![(1.0, 1.0); len];
+
+	let epochs = opts.epochs.unwrap_or(DEFAULT_EPOCHS);
+	for epoch in 0..epochs {
+		let (exaggeration, momentum) = match epoch < EXAGGERATION_EPOCHS {
+			true => (EXAGGERATION, MOMENTUM),
+			false => (1.0, FINAL_MOMENTUM),
+		};
+
+		let mut gradient = vec![(0.0f32, 0.0f32); len];
+		for &(i, j, p) in joint.iter() {
+			let (dx, dy) = (y[i].0 - y[j].0, y[i].1 - y[j].1);
+			let f = exaggeration * p / (1.0 + dx * dx + dy * dy);
+			gradient[i].0 += f * dx;
+			gradient[i].1 += f * dy;
+			gradient[j].0 -= f * dx;
+			gradient[j].1 -= f * dy;
+		}
+
+		let tree = QuadTree::new(&y);
+		let repulsion = y
+			.iter()
+			.map(|p| tree.repulsion(*p, theta))
+			.collect::<Vec<_>>();
+		let sum_q = repulsion
+			.iter()
+			.map(|(_, q)| q)
+			.sum::<f32>()
+			.max(f32::MIN_POSITIVE);
+
+		for i in 0..len {
+			let ((rx, ry), _) = repulsion[i];
+			let g = (gradient[i].0 - rx / sum_q, gradient[i].1 - ry / sum_q);
+
+			// adaptive gains: speed up while the direction stays the same
+			let gain = |gain: f32, g: f32, v: f32| match g.signum() != v.signum() {
+				true => gain + 0.2,
+				false => (gain * 0.8).max(0.01),
+			};
+			gains[i] = (
+				gain(gains[i].0, g.0, velocity[i].0),
+				gain(gains[i].1, g.1, velocity[i].1),
+			);
+
+			velocity[i] = (
+				momentum * velocity[i].0 - LEARNING_RATE * gains[i].0 * g.0,
+				momentum * velocity[i].1 - LEARNING_RATE * gains[i].1 * g.1,
+			);
+			y[i] = (y[i].0 + velocity[i].0, y[i].1 + velocity[i].1);
+		}
+
+		progress.epoch(epoch, epochs, || positions(metadata, &y))?;
+	}
+
+	let mut res = positions(metadata, &y);
+	normalize_positions(&mut res);
+	Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::testing::{clusters, clusters_separated, coordinates, images_at};
+	use super::*;
+
+	const CENTRES: [(f32, f32); 3] = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+	const PER_CLUSTER: usize = 20;
+
+	fn layout(seed: u64) -> Vec<(UuidString, f32, f32)> {
+		let (images, dist) = images_at(&clusters(&CENTRES, PER_CLUSTER, 1.0));
+		let opts = TsneOptions {
+			// only used to pick the distance, which is passed directly here
+			dist: DistanceFunctionVariants::DateTime,
+			perplexity: Some(10.0),
+			epochs: Some(500),
+			theta: None,
+			seed: Some(seed),
+		};
+		tsne(dist, &images, opts, &Progress::default()).unwrap()
+	}
+
+	#[test]
+	fn same_seed_gives_same_layout() {
+		assert_eq!(coordinates(&layout(7)), coordinates(&layout(7)));
+	}
+
+	#[test]
+	fn separated_clusters_stay_separated() {
+		assert!(clusters_separated(&layout(7), PER_CLUSTER));
+	}
+}
@@ -0,0 +1,243 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Image;
+use crate::err::Result;
+
+use super::dist::{DistanceFunction, DistanceFunctionVariants};
+use super::jobs::Progress;
+use super::knn::nearest_neighbours;
+use super::pca::landmark_mds;
+use super::{normalize_positions, positions, UuidString};
+
+const DEFAULT_NEIGHBOURS: usize = 15;
+const DEFAULT_EPOCHS: u32 = 200;
+const DEFAULT_MIN_DIST: f32 = 0.1;
+const NEGATIVE_SAMPLES: usize = 5;
+// the initial layout is scaled to this size
+const INIT_SIZE: f32 = 10.0;
+const INIT_LANDMARKS: usize = 256;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UmapOptions {
+	pub dist: DistanceFunctionVariants,
+	// size of the neighbourhood; larger values keep more of the global structure
+	pub neighbours: Option<usize>,
+	pub epochs: Option<u32>,
+	// how tightly images may be packed together
+	pub min_dist: Option<f32>,
+	pub seed: Option<u64>,
+}
+
+// fuzzy membership of each neighbour; the closest one always has 1, the bandwidth is chosen
+// by binary search so the memberships sum up to log2(k)
+fn memberships(neighbours: &[(usize, f32)]) -> Vec<f32> {
+	let target = (neighbours.len().max(2) as f32).log2();
+	let rho = neighbours
+		.iter()
+		.map(|(_, d)| *d)
+		.find(|d| *d > 0.0)
+		.unwrap_or(0.0);
+
+	let weights = |sigma: f32| {
+		neighbours
+			.iter()
+			.map(|(_, d)| match d.is_finite() {
+				true => (-(d - rho).max(0.0) / sigma).exp(),
+				false => 0.0,
+			})
+			.collect::<Vec<_>>()
+	};
+
+	let (mut lo, mut hi, mut sigma) = (0.0f32, f32::INFINITY, 1.0f32);
+	for _ in 0..64 {
+		let sum = weights(sigma).iter().sum::<f32>();
+		if (sum - target).abs() < 1e-5 {
+			break;
+		}
+		if sum > target {
+			hi = sigma;
+			sigma = (lo + hi) / 2.0;
+		} else {
+			lo = sigma;
+			sigma = match hi.is_finite() {
+				true => (lo + hi) / 2.0,
+				false => sigma * 2.0,
+			};
+		}
+	}
+
+	weights(sigma.max(f32::MIN_POSITIVE))
+}
+
+// parameters of the curve 1 / (1 + a * d^2b) that best fits an offset exponential decay
+// starting at min_dist, found by a coarse then a fine grid search
+fn fit_curve(min_dist: f32) -> (f32, f32) {
+	let xs = (1..=300).map(|i| i as f32 * 0.01).collect::<Vec<_>>();
+	let target = xs
+		.iter()
+		.map(|x| match *x < min_dist {
+			true => 1.0,
+			false => (-(x - min_dist)).exp(),
+		})
+		.collect::<Vec<_>>();
+	let error = |a: f32, b: f32| {
+		xs.iter()
+			.zip(&target)
+			.map(|(x, t)| (1.0 / (1.0 + a * x.powf(2.0 * b)) - t).powi(2))
+			.sum::<f32>()
+	};
+
+	let search = |(a, b): (f32, f32), step: f32| {
+		let mut best = (a, b, error(a, b));
+		for i in -20..=20 {
+			for j in -20..=20 {
+				let (a, b) = (a * (step * i as f32).exp(), b + step * j as f32);
+				if b <= 0.0 {
+					continue;
+				}
+				let e = error(a, b);
+				if e < best.2 {
+					best = (a, b, e);
+				}
+			}
+		}
+		(best.0, best.1)
+	};
+
+	let coarse = search((1.0, 1.0), 0.1);
+	search(coarse, 0.005)
+}
+
+// UMAP over the nearest neighbours, starting from the PCA layout
+pub fn umap<D: DistanceFunction>(
+	dist: D,
+	metadata: &[Image],
+	opts: UmapOptions,
+	progress: &Progress,
+) -> Result<Vec<(UuidString, f32, f32)>> {
+	let len = metadata.len();
+	let seed = opts.seed.unwrap_or_default();
+	let mut rng = StdRng::seed_from_u64(seed);
+	let epochs = opts.epochs.unwrap_or(DEFAULT_EPOCHS);
+	let (a, b) = fit_curve(opts.min_dist.unwrap_or(DEFAULT_MIN_DIST));
+
+	let neighbours = nearest_neighbours(
+		len,
+		opts.neighbours.unwrap_or(DEFAULT_NEIGHBOURS),
+		|i, j| dist.dist(&metadata[i], &metadata[j]).abs(),
+		&mut rng,
+		progress,
+	)?;
+
+	// fuzzy union of the directed memberships: w_ij + w_ji - w_ij * w_ji
+	let mut edges = vec![];
+	for (i, n) in neighbours.iter().enumerate() {
+		progress.check()?;
+		edges.extend(
+			n.iter()
+				.zip(memberships(n))
+				.map(|((j, _), w)| (i.min(*j), i.max(*j), w)),
+		);
+	}
+	edges.sort_unstable_by_key(|(i, j, _)| (*i, *j));
+	let mut graph: Vec<(usize, usize, f32)> = vec![];
+	for (i, j, w) in edges {
+		match graph.last_mut() {
+			Some(last) if last.0 == i && last.1 == j => last.2 = last.2 + w - last.2 * w,
+			_ => graph.push((i, j, w)),
+		}
+	}
+	graph.retain(|(_, _, w)| *w > 0.0);
+
+	let mut y = landmark_mds(&dist, metadata, INIT_LANDMARKS, seed, progress)?
+		.into_iter()
+		.map(|(_, x, y)| (x * INIT_SIZE, y * INIT_SIZE))
+		.collect::<Vec<_>>();
+
+	// edges are sampled in proportion to their weight
+	let max_weight = graph.iter().map(|(_, _, w)| *w).fold(0.0, f32::max);
+	let every = graph
+		.iter()
+		.map(|(_, _, w)| max_weight / w)
+		.collect::<Vec<_>>();
+	let mut next = every.clone();
+
+	let clip = |v: f32| v.clamp(-4.0, 4.0);
+	for epoch in 0..epochs {
+		let alpha = 1.0 - epoch as f32 / epochs as f32;
+		for (e, &(i, j, _)) in graph.iter().enumerate() {
+			if next[e] > epoch as f32 + 1.0 {
+				continue;
+			}
+			next[e] += every[e];
+
+			let (dx, dy) = (y[i].0 - y[j].0, y[i].1 - y[j].1);
+			let d2 = dx * dx + dy * dy;
+			if d2 > 0.0 {
+				let coefficient = -2.0 * a * b * d2.powf(b - 1.0) / (1.0 + a * d2.powf(b));
+				let (gx, gy) = (
+					clip(coefficient * dx) * alpha,
+					clip(coefficient * dy) * alpha,
+				);
+				y[i] = (y[i].0 + gx, y[i].1 + gy);
+				y[j] = (y[j].0 - gx, y[j].1 - gy);
+			}
+
+			for _ in 0..NEGATIVE_SAMPLES {
+				let k = rng.gen_range(0..len);
+				if k == i {
+					continue;
+				}
+				let (dx, dy) = (y[i].0 - y[k].0, y[i].1 - y[k].1);
+				let d2 = dx * dx + dy * dy;
+				let coefficient = 2.0 * b / ((0.001 + d2) * (1.0 + a * d2.powf(b)));
+				let (gx, gy) = match d2 > 0.0 {
+					true => (clip(coefficient * dx), clip(coefficient * dy)),
+					false => (4.0, 4.0),
+				};
+				y[i] = (y[i].0 + gx * alpha, y[i].1 + gy * alpha);
+			}
+		}
+
+		progress.epoch(epoch, epochs, || positions(metadata, &y))?;
+	}
+
+	let mut res = positions(metadata, &y);
+	normalize_positions(&mut res);
+	Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::testing::{clusters, clusters_separated, coordinates, images_at};
+	use super::*;
+
+	const CENTRES: [(f32, f32); 3] = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+	const PER_CLUSTER: usize = 20;
+
+	fn layout(seed: u64) -> Vec<(UuidString, f32, f32)> {
+		let (images, dist) = images_at(&clusters(&CENTRES, PER_CLUSTER, 1.0));
+		let opts = UmapOptions {
+			// only used to pick the distance, which is passed directly here
+			dist: DistanceFunctionVariants::DateTime,
+			neighbours: Some(10),
+			epochs: None,
+			min_dist: None,
+			seed: Some(seed),
+		};
+		umap(dist, &images, opts, &Progress::default()).unwrap()
+	}
+
+	#[test]
+	fn same_seed_gives_same_layout() {
+		assert_eq!(coordinates(&layout(7)), coordinates(&layout(7)));
+	}
+
+	#[test]
+	fn separated_clusters_stay_separated() {
+		assert!(clusters_separated(&layout(7), PER_CLUSTER));
+	}
+}
@@ -1,10 +1,17 @@
 mod atlas;
 mod bulk;
+mod capture;
+mod color;
 mod db;
+mod decode;
+mod duplicates;
 mod err;
 mod layout;
 mod metadata;
+mod phash;
+mod quality;
 mod upload;
+mod video;
 
 use db::{DbExtension, Image, ImageFile};
 use err::Result;
@@ -14,9 +21,6 @@ use axum::{
 	routing::{get, post},
 	Extension,
 };
-use dotenv;
-use env_logger;
-
 use std::{
 	net::SocketAddr,
 	path::{Path, PathBuf},
@@ -29,11 +33,12 @@ use uuid::Uuid;
 const IMAGES_PATH: &str = "./images";
 const RESPONSE_MAX_SIZE: u64 = 512 * 1024 * 1024;
 const STATIC_ATLASES_DIR: &str = "./images/atlases";
+const ATLAS_MANIFEST_FILE: &str = "manifest.msgp";
 
 fn uuid_to_string(id: &Uuid) -> String {
 	let mut id_buf = Uuid::encode_buffer();
 	let id_str = id.hyphenated().encode_lower(&mut id_buf);
-	return id_str.to_string();
+	id_str.to_string()
 }
 
 fn uuid_to_string_serialize<S>(id: &Uuid, ser: S) -> Result<S::Ok, S::Error>
@@ -44,11 +49,31 @@ where
 	ser.serialize_str(&id_str)
 }
 
-fn get_static_atlas_path(collection_id: Uuid) -> PathBuf {
+// counterpart of uuid_to_string_serialize; the derived Deserialize expects bytes in MessagePack
+fn uuid_from_string_deserialize<'de, D>(de: D) -> Result<Uuid, D::Error>
+where
+	D: serde::Deserializer<'de>,
+{
+	let id_str = <std::borrow::Cow<str> as serde::Deserialize>::deserialize(de)?;
+	Uuid::parse_str(&id_str).map_err(serde::de::Error::custom)
+}
+
+fn get_static_atlas_dir(collection_id: Uuid) -> PathBuf {
 	let mut path = PathBuf::new();
 	path.push(STATIC_ATLASES_DIR);
 	path.push(uuid_to_string(&collection_id));
-	path.set_extension("msgp");
+	path
+}
+
+fn get_static_atlas_version_dir(collection_id: Uuid, version: i32) -> PathBuf {
+	let mut path = get_static_atlas_dir(collection_id);
+	path.push(version.to_string());
+	path
+}
+
+fn get_static_atlas_page_path(atlas_dir: &Path, n: usize, extension: &str) -> PathBuf {
+	let mut path = atlas_dir.join(n.to_string());
+	path.set_extension(extension);
 	path
 }
 
@@ -94,11 +119,54 @@ async fn main() {
 		.route("/:id", get(crate::metadata::get_images))
 		.route("/:id/metadata", get(crate::metadata::get_image_metadata))
 		.route("/:id/duplicate", post(crate::upload::duplicate))
+		.route(
+			"/:id/near-duplicates",
+			get(crate::duplicates::get_near_duplicates),
+		)
 		.route("/:id/upload", post(crate::upload::upload_image))
 		.route("/:id/finalize", post(crate::upload::finalize_collection))
+		.route(
+			"/:id/thumbnails",
+			get(crate::metadata::get_thumbnail_settings)
+				.put(crate::metadata::set_thumbnail_settings),
+		)
+		.route(
+			"/:id/thumbnails/regenerate",
+			post(crate::upload::regenerate_thumbnails),
+		)
+		.route(
+			"/:id/images/:image_id/frames/:n",
+			get(crate::upload::get_image_frame),
+		)
 		.route("/:id/bulk", post(crate::bulk::get_images_bulk))
-		.route("/:id/atlas", get(crate::atlas::get_static_atlas))
+		.route(
+			"/:id/atlas",
+			get(crate::atlas::get_static_atlas).post(crate::atlas::get_ordered_atlas),
+		)
+		.route(
+			"/:id/atlas/manifest",
+			get(crate::atlas::get_static_atlas_manifest),
+		)
+		.route(
+			"/:id/atlas/:version/pages/:n",
+			get(crate::atlas::get_static_atlas_page),
+		)
 		.route("/:id/layout", post(crate::layout::get_layout))
+		.route(
+			"/:id/layout/jobs/:job_id",
+			get(crate::layout::get_job_status).delete(crate::layout::cancel_job),
+		)
+		.route(
+			"/:id/layout/jobs/:job_id/progress",
+			get(crate::layout::watch_job),
+		)
+		.route("/:id/layouts", get(crate::layout::get_saved_layouts))
+		.route(
+			"/:id/layouts/:layout_id",
+			get(crate::layout::get_stored_layout)
+				.put(crate::layout::save_layout)
+				.delete(crate::layout::delete_stored_layout),
+		)
 		.layer(db_extension)
 		.layer(CorsLayer::permissive());
 
@@ -1,11 +1,12 @@
 use std::collections::HashMap;
 
 use axum::extract::Path;
+use axum::http::StatusCode;
 use axum::{Extension, Json};
 use uuid::Uuid;
 
-use crate::db::{Collection, DbExtension, Image, ImageMetadata, NewCollection};
-use crate::err::Result;
+use crate::db::{Collection, DbExtension, Image, ImageMetadata, NewCollection, ThumbnailSettings};
+use crate::err::{Error, Result};
 
 #[derive(serde::Serialize)]
 pub struct ImageMetadataResponse(Uuid, u32, u32);
@@ -43,13 +44,69 @@ pub async fn get_collections(Extension(db): DbExtension) -> Result<Json<Vec<Coll
 #[derive(serde::Deserialize)]
 pub struct CreateCollectionRequest {
 	name: String,
+	thumbnail_settings: Option<ThumbnailSettings>,
 }
 
 pub async fn create_collection(
 	Extension(db): DbExtension,
 	Json(req): Json<CreateCollectionRequest>,
 ) -> Result<Json<Collection>> {
+	let thumbnail_settings = req.thumbnail_settings.unwrap_or_default();
+	validate_thumbnail_settings(&thumbnail_settings)?;
+
 	Ok(Json(
-		NewCollection { name: req.name }.insert_one(&db).await?,
+		NewCollection {
+			name: req.name,
+			thumbnail_settings,
+		}
+		.insert_one(&db)
+		.await?,
 	))
 }
+
+fn validate_thumbnail_settings(settings: &ThumbnailSettings) -> Result<()> {
+	if settings.sizes.is_empty() || settings.sizes.contains(&0) {
+		return Err(Error::Custom(
+			StatusCode::BAD_REQUEST,
+			"thumbnail sizes must be non-empty and positive".into(),
+		));
+	}
+
+	if !(1..=100).contains(&settings.quality) {
+		return Err(Error::Custom(
+			StatusCode::BAD_REQUEST,
+			"thumbnail quality must be between 1 and 100".into(),
+		));
+	}
+
+	Ok(())
+}
+
+pub async fn get_thumbnail_settings(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+) -> Result<Json<ThumbnailSettings>> {
+	let collection = Collection::get_by_id(&db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	Ok(Json(collection.thumbnail_settings.0))
+}
+
+// existing thumbnails are only replaced by regenerating them
+pub async fn set_thumbnail_settings(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+	Json(settings): Json<ThumbnailSettings>,
+) -> Result<Json<Collection>> {
+	validate_thumbnail_settings(&settings)?;
+
+	let mut collection = Collection::get_by_id(&db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	collection.thumbnail_settings.0 = settings;
+	collection.save(&db).await?;
+
+	Ok(Json(collection))
+}
@@ -0,0 +1,172 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage};
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKind {
+	Average,
+	Difference,
+	#[default]
+	Perceptual,
+}
+
+// 64 bit hashes, serialized as hex since JSON numbers can't hold them
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct PerceptualHashes {
+	#[serde(with = "hex_u64")]
+	pub ahash: u64,
+	#[serde(with = "hex_u64")]
+	pub dhash: u64,
+	#[serde(with = "hex_u64")]
+	pub phash: u64,
+}
+
+mod hex_u64 {
+	use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer>(value: &u64, ser: S) -> Result<S::Ok, S::Error> {
+		ser.serialize_str(&format!("{:016x}", value))
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<u64, D::Error> {
+		let s = String::deserialize(de)?;
+		u64::from_str_radix(&s, 16).map_err(D::Error::custom)
+	}
+}
+
+impl PerceptualHashes {
+	pub fn new(img: &DynamicImage) -> Self {
+		let gray = img.to_luma8();
+		Self {
+			ahash: average_hash(&gray),
+			dhash: difference_hash(&gray),
+			phash: perceptual_hash(&gray),
+		}
+	}
+
+	pub fn get(&self, kind: HashKind) -> u64 {
+		match kind {
+			HashKind::Average => self.ahash,
+			HashKind::Difference => self.dhash,
+			HashKind::Perceptual => self.phash,
+		}
+	}
+}
+
+pub fn hamming(h1: u64, h2: u64) -> u32 {
+	(h1 ^ h2).count_ones()
+}
+
+fn bits<I: Iterator<Item = bool>>(bits: I) -> u64 {
+	bits.take(64)
+		.enumerate()
+		.fold(0, |acc, (i, bit)| acc | (bit as u64) << i)
+}
+
+// pixels brighter than the mean of an 8x8 version
+fn average_hash(gray: &GrayImage) -> u64 {
+	let small = imageops::resize(gray, 8, 8, FilterType::Triangle);
+	let mean = small.pixels().map(|p| p.0[0] as u32).sum::<u32>() / 64;
+	bits(small.pixels().map(|p| p.0[0] as u32 > mean))
+}
+
+// gradients between horizontal neighbours of a 9x8 version
+fn difference_hash(gray: &GrayImage) -> u64 {
+	let small = imageops::resize(gray, 9, 8, FilterType::Triangle);
+	bits((0..8).flat_map(|y| {
+		let small = &small;
+		(0..8).map(move |x| small.get_pixel(x, y).0[0] < small.get_pixel(x + 1, y).0[0])
+	}))
+}
+
+// low frequencies of the DCT of a 32x32 version, compared to their median
+fn perceptual_hash(gray: &GrayImage) -> u64 {
+	const N: usize = 32;
+	const LOW: usize = 8;
+
+	let small = imageops::resize(gray, N as u32, N as u32, FilterType::Triangle);
+	let pixels = small.pixels().map(|p| p.0[0] as f32).collect::<Vec<_>>();
+
+	// only the lowest frequencies are needed
+	let cos = (0..LOW)
+		.map(|u| {
+			(0..N)
+				.map(|x| {
+					(std::f32::consts::PI * u as f32 * (2 * x + 1) as f32 / (2 * N) as f32).cos()
+				})
+				.collect::<Vec<_>>()
+		})
+		.collect::<Vec<_>>();
+
+	// separable 2D DCT-II: rows first, then columns
+	let rows = (0..N)
+		.flat_map(|y| {
+			let (pixels, cos) = (&pixels, &cos);
+			(0..LOW).map(move |u| (0..N).map(|x| pixels[y * N + x] * cos[u][x]).sum::<f32>())
+		})
+		.collect::<Vec<_>>();
+	let dct = (0..LOW)
+		.flat_map(|v| {
+			let (rows, cos) = (&rows, &cos);
+			(0..LOW).map(move |u| (0..N).map(|y| rows[y * LOW + u] * cos[v][y]).sum::<f32>())
+		})
+		.collect::<Vec<_>>();
+
+	// the DC term only holds the average brightness
+	let mut sorted = dct[1..].to_vec();
+	sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+	let median = sorted[sorted.len() / 2];
+
+	bits(dct.iter().map(|c| *c > median))
+}
+
+#[cfg(test)]
+mod tests {
+	use image::{GrayImage, Luma};
+
+	use super::*;
+
+	// a few soft blobs on a gradient, something like a photo without its details
+	fn scene(size: u32) -> DynamicImage {
+		let blobs = [(0.3, 0.3, 0.15), (0.7, 0.4, 0.1), (0.4, 0.75, 0.2)];
+		GrayImage::from_fn(size, size, |x, y| {
+			let (x, y) = (x as f32 / size as f32, y as f32 / size as f32);
+			let blob = blobs
+				.iter()
+				.map(|(bx, by, r)| (1.0 - ((x - bx).hypot(y - by) / r).powi(2)).max(0.0))
+				.sum::<f32>();
+			Luma([(60.0 + 80.0 * x + 100.0 * blob.min(1.0)) as u8])
+		})
+		.into()
+	}
+
+	fn distances(a: &DynamicImage, b: &DynamicImage) -> [u32; 3] {
+		let (a, b) = (PerceptualHashes::new(a), PerceptualHashes::new(b));
+		[
+			HashKind::Average,
+			HashKind::Difference,
+			HashKind::Perceptual,
+		]
+		.map(|kind| hamming(a.get(kind), b.get(kind)))
+	}
+
+	#[test]
+	fn edited_copies_are_near_duplicates() {
+		let original = scene(256);
+		let brighter =
+			DynamicImage::ImageLuma8(imageops::colorops::brighten(&original.to_luma8(), 20));
+		let smaller = original.resize(120, 120, FilterType::Lanczos3);
+		let blurred = original.blur(1.5);
+		for copy in [&brighter, &smaller, &blurred] {
+			assert!(distances(&original, copy).iter().all(|d| *d <= 4));
+		}
+	}
+
+	#[test]
+	fn different_images_are_far_apart() {
+		let original = scene(256);
+		for other in [original.fliph(), original.rotate90()] {
+			assert!(distances(&original, &other).iter().all(|d| *d >= 16));
+		}
+	}
+}
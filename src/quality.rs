@@ -0,0 +1,104 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+pub const HISTOGRAM_BINS: usize = 32;
+
+// larger images are scaled down to fit this size first, so sharpness and noise are measured
+// at similar scales regardless of the resolution of the original; smaller ones aren't scaled up,
+// as interpolation would smooth away the noise and detail that are measured
+const ANALYSIS_SIZE: u32 = 1024;
+
+// luminance values at most / at least these count as clipped
+const CLIP_SHADOWS: u8 = 2;
+const CLIP_HIGHLIGHTS: u8 = 253;
+
+// scores used to cull blurry or badly exposed images; luminance values are in 0..1
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct QualityMetrics {
+	// variance of the Laplacian; blurry images score low
+	pub sharpness: f32,
+	// standard deviation of the sensor noise, estimated over the whole image (Immerkær),
+	// so strong edges and fine texture raise it too
+	pub noise: f32,
+	pub brightness: f32,
+	// RMS contrast, i.e. standard deviation of the luminance
+	pub contrast: f32,
+	// percentage of pixels within 2 levels of black or white
+	pub clipping: f32,
+	// fractions of pixels per luminance bin, summing up to 1
+	pub histogram: Vec<f32>,
+}
+
+// 3x3 convolution over the inner pixels of the image
+fn convolve(gray: &GrayImage, kernel: [[f32; 3]; 3]) -> Vec<f32> {
+	let (width, height) = gray.dimensions();
+	let mut res = Vec::with_capacity((width.saturating_sub(2) * height.saturating_sub(2)) as usize);
+	for y in 1..height.saturating_sub(1) {
+		for x in 1..width.saturating_sub(1) {
+			let mut sum = 0.0;
+			for (j, row) in kernel.iter().enumerate() {
+				for (i, k) in row.iter().enumerate() {
+					let p = gray.get_pixel(x + i as u32 - 1, y + j as u32 - 1).0[0];
+					sum += *k * p as f32 / 255.0;
+				}
+			}
+			res.push(sum);
+		}
+	}
+
+	res
+}
+
+fn mean_and_variance<I: Iterator<Item = f32> + Clone>(values: I) -> (f32, f32) {
+	let count = values.clone().count().max(1) as f32;
+	let mean = values.clone().sum::<f32>() / count;
+	let variance = values.map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+	(mean, variance)
+}
+
+impl QualityMetrics {
+	// expects the decoded original rather than a lossy thumbnail
+	pub fn new(img: &DynamicImage) -> Self {
+		let gray = match img.width() > ANALYSIS_SIZE || img.height() > ANALYSIS_SIZE {
+			true => img
+				.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, FilterType::Lanczos3)
+				.to_luma8(),
+			false => img.to_luma8(),
+		};
+		let pixels = gray.pixels().map(|p| p.0[0]);
+		let len = pixels.len().max(1) as f32;
+
+		let laplacian = convolve(&gray, [[0.0, 1.0, 0.0], [1.0, -4.0, 1.0], [0.0, 1.0, 0.0]]);
+		let (_, sharpness) = mean_and_variance(laplacian.iter().copied());
+
+		// Immerkær: the difference of two Laplacians cancels out image structure
+		let noise_kernel = convolve(
+			&gray,
+			[[1.0, -2.0, 1.0], [-2.0, 4.0, -2.0], [1.0, -2.0, 1.0]],
+		);
+		let noise = noise_kernel.iter().map(|v| v.abs()).sum::<f32>()
+			* (std::f32::consts::FRAC_PI_2).sqrt()
+			/ (6.0 * noise_kernel.len().max(1) as f32);
+
+		let (brightness, variance) = mean_and_variance(pixels.clone().map(|p| p as f32 / 255.0));
+
+		let clipped = pixels
+			.clone()
+			.filter(|p| *p <= CLIP_SHADOWS || *p >= CLIP_HIGHLIGHTS)
+			.count();
+
+		let mut histogram = vec![0.0; HISTOGRAM_BINS];
+		for p in pixels {
+			histogram[p as usize * HISTOGRAM_BINS / 256] += 1.0 / len;
+		}
+
+		Self {
+			sharpness,
+			noise,
+			brightness,
+			contrast: variance.sqrt(),
+			clipping: clipped as f32 * 100.0 / len,
+			histogram,
+		}
+	}
+}
@@ -1,9 +1,15 @@
-use std::{io::Cursor, path::PathBuf};
+use std::{collections::HashMap, io::Cursor, path::PathBuf, sync::Arc};
 
-use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use axum::{
+	extract::Path,
+	http::{header, StatusCode},
+	response::IntoResponse,
+	Extension, Json,
+};
 use fast_image_resize as resize;
-use futures_util::TryStreamExt;
-use image::io::Reader as ImageReader;
+use futures_util::{StreamExt, TryStreamExt};
+use image::codecs::{jpeg::JpegEncoder, png::PngEncoder};
+use image::ImageEncoder;
 use tokio::{
 	fs::File,
 	io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
@@ -12,9 +18,17 @@ use uuid::Uuid;
 
 use crate::{
 	atlas::regenerate_static_atlas,
-	db::{Collection, Db, DbExtension, Image, ImageFile, ImageFileKind, NewImage},
+	capture::{read_date_time, CaptureInfo, GpsPosition},
+	color,
+	db::{
+		AlphaMode, Collection, Db, DbExtension, FrameSelection, Image, ImageFile, ImageFileAlpha,
+		ImageFileKind, NewImage, ResizeAlgorithm, ThumbnailFormat, ThumbnailSettings,
+	},
+	decode::{decode_original, decode_original_frame, read_orientation, DecodedImage, Orientation},
 	err::{Error, Result},
-	IMAGES_PATH,
+	phash::PerceptualHashes,
+	quality::QualityMetrics,
+	video, IMAGES_PATH,
 };
 
 lazy_static::lazy_static! {
@@ -27,9 +41,64 @@ lazy_static::lazy_static! {
 			resize::CpuExtensions::None
 		}
 	};
+
+	// serializes thumbnail regeneration per collection
+	static ref THUMBNAIL_LOCKS: std::sync::Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<()>>>> =
+		Default::default();
+}
+
+fn thumbnail_lock(collection_id: Uuid) -> Arc<tokio::sync::Mutex<()>> {
+	THUMBNAIL_LOCKS
+		.lock()
+		.unwrap()
+		.entry(collection_id)
+		.or_default()
+		.clone()
+}
+
+// encode derivative pixels in the configured format; JPEG can't keep transparency
+fn encode_image<W: std::io::Write>(
+	writer: &mut W,
+	buf: &[u8],
+	width: u32,
+	height: u32,
+	settings: &ThumbnailSettings,
+	alpha: ImageFileAlpha,
+) -> Result<ThumbnailFormat> {
+	let (format, color) = match alpha {
+		ImageFileAlpha::Kept => (ThumbnailFormat::Png, image::ColorType::Rgba8),
+		_ => (settings.format, image::ColorType::Rgb8),
+	};
+
+	match format {
+		ThumbnailFormat::Jpeg => JpegEncoder::new_with_quality(writer, settings.quality)
+			.encode(buf, width, height, color)?,
+		ThumbnailFormat::Png => PngEncoder::new(writer).write_image(buf, width, height, color)?,
+	}
+
+	Ok(format)
 }
 
-pub const THUMBNAIL_FORMAT: image::ImageFormat = image::ImageFormat::Jpeg;
+// encode and store a derivative; its extension is set to the format actually used
+async fn store_derivative(
+	db: &Db,
+	mut image_file: ImageFile,
+	buf: &[u8],
+	settings: &ThumbnailSettings,
+) -> Result<ImageFile> {
+	let mut img_buf = vec![];
+	let (width, height, alpha) = (image_file.width, image_file.height, image_file.alpha);
+	let format = encode_image(&mut img_buf, buf, width, height, settings, alpha)?;
+	image_file.extension = format.image_format().extensions_str()[0].to_owned();
+
+	// Write destination image
+	std::fs::write(image_file.get_path(), &img_buf)?;
+
+	// If this succeeded, save entry in db
+	image_file.clone().insert_one(db).await?;
+
+	Ok(image_file)
+}
 
 pub async fn save_image(
 	db: &Db,
@@ -37,121 +106,478 @@ pub async fn save_image(
 	width: u32,
 	height: u32,
 	id: Uuid,
-	format: image::ImageFormat,
-	color: image::ColorType,
-) -> Result<(), Error> {
-	// Write destination image as JPG-file
+	settings: &ThumbnailSettings,
+	alpha: ImageFileAlpha,
+) -> Result<ImageFile, Error> {
 	let image_file = ImageFile {
 		image_id: id,
 		width,
 		height,
-		extension: format.extensions_str()[0].to_owned(),
+		extension: String::new(),
 		kind: ImageFileKind::Thumbnail,
+		alpha,
+		frame: 0,
 	};
 
-	let path = image_file.get_path();
-	image::save_buffer_with_format(path, buf, width, height, color, format)?;
+	store_derivative(db, image_file, buf, settings).await
+}
 
-	// If this succeeded, save entry in db
-	image_file.insert_one(db).await?;
+// largest size with the aspect ratio of `size` that fits into `bounds`, None if it would upscale
+pub fn largest_that_fits((width, height): (u32, u32), (w, h): (u32, u32)) -> Option<(u32, u32)> {
+	let wr = (width as f32) / (w as f32);
+	let hr = (height as f32) / (h as f32);
+	let m = f32::max(wr, hr);
+
+	// don't attempt upscaling
+	if m <= 1. {
+		None
+	} else {
+		Some((
+			((width as f32 / m) as u32).max(1),
+			((height as f32 / m) as u32).max(1),
+		))
+	}
+}
 
-	Ok(())
+// blend transparent pixels onto an opaque background
+fn flatten_alpha(img: image::RgbaImage, (r, g, b): (u8, u8, u8)) -> image::RgbImage {
+	let blend = |c: u8, bg: u8, a: u8| {
+		((c as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8
+	};
+
+	image::RgbImage::from_fn(img.width(), img.height(), |x, y| {
+		let image::Rgba([pr, pg, pb, pa]) = *img.get_pixel(x, y);
+		image::Rgb([blend(pr, r, pa), blend(pg, g, pa), blend(pb, b, pa)])
+	})
 }
 
-pub async fn save_image_thumbnails(
-	db: &Db,
-	meta: Image,
+// pixels of derivatives; RGBA if transparency is kept, RGB otherwise
+fn derivative_pixels(
 	img: image::DynamicImage,
-) -> Result<(), Error> {
-	measure_time::warn_time!("saving images");
+	settings: &ThumbnailSettings,
+) -> (Vec<u8>, ImageFileAlpha) {
+	match (img.color().has_alpha(), settings.alpha) {
+		(false, _) => (img.into_rgb8().into_raw(), ImageFileAlpha::Opaque),
+		(true, AlphaMode::Keep) => (img.into_rgba8().into_raw(), ImageFileAlpha::Kept),
+		(true, AlphaMode::Flatten) => (
+			flatten_alpha(img.into_rgba8(), settings.background).into_raw(),
+			ImageFileAlpha::Flattened,
+		),
+	}
+}
 
-	// make sure format is rgb8
-	let img: image::DynamicImage = match img.as_rgb8() {
-		Some(_) => img,
-		None => img.to_rgb8().into(),
+// source of thumbnails
+fn to_resize_image(
+	img: image::DynamicImage,
+	settings: &ThumbnailSettings,
+) -> Result<(resize::Image<'static>, ImageFileAlpha)> {
+	let width = std::num::NonZeroU32::new(img.width()).unwrap();
+	let height = std::num::NonZeroU32::new(img.height()).unwrap();
+
+	let (buf, alpha) = derivative_pixels(img, settings);
+	let pixel_type = match alpha {
+		ImageFileAlpha::Kept => resize::PixelType::U8x4,
+		_ => resize::PixelType::U8x3,
 	};
 
-	let width_ = std::num::NonZeroU32::new(img.width()).unwrap();
-	let height_ = std::num::NonZeroU32::new(img.height()).unwrap();
+	let mut src_image = resize::Image::from_vec_u8(width, height, buf, pixel_type)?;
+
+	// premultiply, so colours of transparent pixels don't bleed into their neighbours
+	if alpha == ImageFileAlpha::Kept {
+		resize::MulDiv::default().multiply_alpha_inplace(&mut src_image.view_mut())?;
+	}
+
+	Ok((src_image, alpha))
+}
 
-	let src_image = resize::Image::from_vec_u8(
-		width_,
-		height_,
-		img.to_rgb8().into_raw(),
-		resize::PixelType::U8x3,
-	)?;
+fn resize_image(
+	src_image: &resize::Image,
+	width: u32,
+	height: u32,
+	alg: ResizeAlgorithm,
+) -> Result<resize::Image<'static>> {
+	measure_time::warn_time!(
+		"resizing {}x{} -> {}x{}",
+		src_image.width(),
+		src_image.height(),
+		width,
+		height
+	);
 
-	let width = img.width();
-	let height = img.height();
+	let dst_width = std::num::NonZeroU32::new(width).unwrap();
+	let dst_height = std::num::NonZeroU32::new(height).unwrap();
+	let mut dst_image = resize::Image::new(dst_width, dst_height, src_image.pixel_type());
 
-	let largest_that_fits = |(w, h): (u32, u32)| {
-		let wr = (width as f32) / (w as f32);
-		let hr = (height as f32) / (h as f32);
-		let m = f32::max(wr, hr);
+	let mut dst_view = dst_image.view_mut();
 
-		// don't attempt upscaling
-		if m <= 1. {
-			None
-		} else {
-			Some(((width as f32 / m) as u32, (height as f32 / m) as u32))
-		}
-	};
+	let mut resizer = resize::Resizer::new(alg.resize_alg());
 
-	let sizes = [
-		// save small thumbnail for static atlas
-		largest_that_fits((30, 30)),
-		// save large thumbnail
-		largest_that_fits((500, 500)),
-		// save giga thumbnail
-		largest_that_fits((1000, 1000)),
-	];
-
-	for size in sizes {
-		measure_time::warn_time!(
-			"resizing {}x{} -> {}x{}",
-			img.width(),
-			img.height(),
-			width,
-			height
-		);
+	// @SAFETY
+	// an unsupported CPU extension will only be set if it is incorrectly reported
+	// RESIZE_CPU_EXTENSION checks at runtime, and only keeps supported extensions
+	unsafe {
+		resizer.set_cpu_extensions(*RESIZE_CPU_EXTENSION);
+	}
+	resizer.resize(&src_image.view(), &mut dst_view).unwrap();
+
+	// undo premultiplication of the source
+	if dst_image.pixel_type() == resize::PixelType::U8x4 {
+		resize::MulDiv::default().divide_alpha_inplace(&mut dst_image.view_mut())?;
+	}
 
+	Ok(dst_image)
+}
+
+pub async fn save_image_thumbnails(
+	db: &Db,
+	id: Uuid,
+	img: image::DynamicImage,
+	settings: &ThumbnailSettings,
+) -> Result<Vec<ImageFile>, Error> {
+	measure_time::warn_time!("saving images");
+
+	let size = (img.width(), img.height());
+	let (src_image, alpha) = to_resize_image(img, settings)?;
+
+	let mut image_files = vec![];
+	for &bounds in settings.sizes.iter() {
 		// only count in sizes smaller than the original image
-		let (width, height) = match size {
+		let (width, height) = match largest_that_fits(size, (bounds, bounds)) {
 			Some(size) => size,
 			None => continue,
 		};
 
-		let dst_width = std::num::NonZeroU32::new(width).unwrap();
-		let dst_height = std::num::NonZeroU32::new(height).unwrap();
-		let mut dst_image = resize::Image::new(dst_width, dst_height, src_image.pixel_type());
+		let dst_image = resize_image(&src_image, width, height, settings.resize_alg)?;
 
-		let mut dst_view = dst_image.view_mut();
+		let image_file =
+			save_image(db, dst_image.buffer(), width, height, id, settings, alpha).await?;
+		image_files.push(image_file);
+	}
 
-		let mut resizer = resize::Resizer::new(resize::ResizeAlg::Nearest);
+	Ok(image_files)
+}
 
-		// @SAFETY
-		// an unsupported CPU extension will only be set if it is incorrectly reported
-		// RESIZE_CPU_EXTENSION checks at runtime, and only keeps supported extensions
-		unsafe {
-			resizer.set_cpu_extensions(**&RESIZE_CPU_EXTENSION);
-		}
-		resizer.resize(&src_image.view(), &mut dst_view).unwrap();
+// keyframes of a video, at the largest thumbnail size
+pub async fn save_video_keyframes(
+	db: &Db,
+	id: Uuid,
+	path: &std::path::Path,
+	settings: &ThumbnailSettings,
+) -> Result<Vec<ImageFile>> {
+	measure_time::warn_time!("saving keyframes");
+
+	let (video, _) = video::probe(path).await?;
+	let bounds = settings.sizes.iter().copied().max().unwrap_or(500);
 
-		save_image(
-			&db,
-			dst_image.buffer(),
+	let mut image_files = vec![];
+	for n in 0..video.keyframe_count {
+		let img = video::extract_frame(path, video::keyframe_timestamp_ms(&video, n)).await?;
+
+		let size = (img.width(), img.height());
+		let (width, height) = largest_that_fits(size, (bounds, bounds)).unwrap_or(size);
+		let (src_image, alpha) = to_resize_image(img, settings)?;
+		let dst_image = resize_image(&src_image, width, height, settings.resize_alg)?;
+
+		let image_file = ImageFile {
+			image_id: id,
 			width,
 			height,
-			meta.id,
-			THUMBNAIL_FORMAT,
-			image::ColorType::Rgb8,
-		)
-		.await?;
+			extension: String::new(),
+			kind: ImageFileKind::Keyframe,
+			alpha,
+			frame: n,
+		};
+		image_files.push(store_derivative(db, image_file, dst_image.buffer(), settings).await?);
+	}
+
+	Ok(image_files)
+}
+
+// videos can only be decoded from a file, so uploads are decoded from a temporary copy
+async fn decode_upload(
+	data: &[u8],
+	extension: Option<&str>,
+	frame: FrameSelection,
+) -> Result<DecodedImage> {
+	match extension {
+		Some(extension) if video::is_video_extension(extension) => {
+			let path = std::env::temp_dir().join(format!(
+				"megallery-{}.{}",
+				crate::uuid_to_string(&Uuid::new_v4()),
+				extension
+			));
+			tokio::fs::write(&path, data).await?;
+			let decoded = video::decode_video(&path, extension, frame).await;
+			tokio::fs::remove_file(&path).await?;
+			decoded
+		}
+		_ => decode_original(data, extension, frame, true),
+	}
+}
+
+// decode the original, the frame thumbnails are made of is chosen by the collection settings
+// frames are only counted if requested, as it needs a pass over the whole file
+pub async fn load_original_decoded(
+	db: &Db,
+	image_id: Uuid,
+	with_frames: bool,
+) -> Result<DecodedImage> {
+	let original = ImageFile::get_original(db, image_id)
+		.await?
+		.ok_or(Error::NotFound("original image file".into()))?;
+	let frame = get_thumbnail_settings(db, image_id).await?.frame;
+
+	if video::is_video_extension(&original.extension) {
+		return video::decode_video(&original.get_path(), &original.extension, frame).await;
+	}
+
+	let data = tokio::fs::read(original.get_path()).await?;
+	tokio::task::spawn_blocking(move || {
+		decode_original(&data, Some(&original.extension), frame, with_frames)
+	})
+	.await?
+}
+
+pub async fn load_original(db: &Db, image_id: Uuid) -> Result<image::DynamicImage> {
+	Ok(load_original_decoded(db, image_id, false).await?.image)
+}
+
+async fn get_thumbnail_settings(db: &Db, image_id: Uuid) -> Result<ThumbnailSettings> {
+	let image = Image::get_by_id(db, image_id)
+		.await?
+		.ok_or(Error::NotFound("image".into()))?;
+	let collection = Collection::get_by_id(db, image.collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	Ok(collection.thumbnail_settings.0)
+}
+
+fn to_dynamic_image(
+	width: u32,
+	height: u32,
+	buf: Vec<u8>,
+	alpha: ImageFileAlpha,
+) -> Result<image::DynamicImage> {
+	let img = match alpha {
+		ImageFileAlpha::Kept => image::RgbaImage::from_raw(width, height, buf)
+			.ok_or(Error::GenericInternalError)?
+			.into(),
+		_ => image::RgbImage::from_raw(width, height, buf)
+			.ok_or(Error::GenericInternalError)?
+			.into(),
+	};
+
+	Ok(img)
+}
+
+// generate a single thumbnail from the loaded original on demand, e.g. if it went missing
+pub async fn generate_thumbnail(
+	db: &Db,
+	image_id: Uuid,
+	img: image::DynamicImage,
+	width: u32,
+	height: u32,
+) -> Result<image::DynamicImage> {
+	let settings = get_thumbnail_settings(db, image_id).await?;
+
+	// small images don't have thumbnails, use the original
+	if (img.width(), img.height()) == (width, height) {
+		let img = tokio::task::spawn_blocking(move || {
+			let (buf, alpha) = derivative_pixels(img, &settings);
+			to_dynamic_image(width, height, buf, alpha)
+		})
+		.await??;
+
+		return Ok(img);
+	}
+
+	let (dst_image, alpha, settings) = tokio::task::spawn_blocking(move || {
+		let (src_image, alpha) = to_resize_image(img, &settings)?;
+		let dst_image = resize_image(&src_image, width, height, settings.resize_alg)?;
+		Ok::<_, Error>((dst_image, alpha, settings))
+	})
+	.await??;
+
+	save_image(
+		db,
+		dst_image.buffer(),
+		width,
+		height,
+		image_id,
+		&settings,
+		alpha,
+	)
+	.await?;
+
+	to_dynamic_image(width, height, dst_image.into_vec(), alpha)
+}
+
+// serve a single frame of an animation or page of a multi-page document, at full size
+pub async fn get_image_frame(
+	Extension(db): DbExtension,
+	Path((collection_id, image_id, n)): Path<(Uuid, Uuid, u32)>,
+) -> Result<impl IntoResponse> {
+	let collection = Collection::get_by_id(&db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+	let image = Image::get_by_id(&db, image_id)
+		.await?
+		.filter(|image| image.collection_id == collection_id)
+		.ok_or(Error::NotFound("image".into()))?;
+	let original = ImageFile::get_original(&db, image.id)
+		.await?
+		.ok_or(Error::NotFound("original image file".into()))?;
+
+	// keyframes of videos are extracted on upload
+	if video::is_video_extension(&original.extension) {
+		let keyframe = ImageFile::get_keyframe(&db, image.id, n)
+			.await?
+			.ok_or_else(|| Error::NotFound(format!("frame {}", n)))?;
+		let mime = match keyframe.extension.as_str() {
+			"png" => "image/png",
+			_ => "image/jpeg",
+		};
+		let data = tokio::fs::read(keyframe.get_path()).await?;
+		return Ok(([(header::CONTENT_TYPE, mime)], data));
+	}
+
+	let data = tokio::fs::read(original.get_path()).await?;
+	let settings = collection.thumbnail_settings.0;
+	let (img_buf, format) = tokio::task::spawn_blocking(move || {
+		let img = decode_original_frame(&data, Some(&original.extension), n)?
+			.ok_or_else(|| Error::NotFound(format!("frame {}", n)))?;
+
+		let (width, height) = (img.width(), img.height());
+		let (buf, alpha) = derivative_pixels(img, &settings);
+
+		let mut img_buf = vec![];
+		let format = encode_image(&mut img_buf, &buf, width, height, &settings, alpha)?;
+		Ok::<_, Error>((img_buf, format))
+	})
+	.await??;
+
+	let mime = match format {
+		ThumbnailFormat::Jpeg => "image/jpeg",
+		ThumbnailFormat::Png => "image/png",
+	};
+
+	Ok(([(header::CONTENT_TYPE, mime)], img_buf))
+}
+
+// regenerate thumbnails from the originals after the thumbnail settings changed
+// derivatives may already be gone, e.g. after an interrupted regeneration
+async fn remove_derivative(file: &ImageFile) -> Result<()> {
+	match tokio::fs::remove_file(file.get_path()).await {
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+		res => Ok(res?),
+	}
+}
+
+async fn regenerate_image_thumbnails(
+	db: &Db,
+	mut image: Image,
+	settings: &ThumbnailSettings,
+) -> Result<()> {
+	let old_files = ImageFile::get_by_image_id(db, image.id).await?;
+
+	let img = load_original(db, image.id).await?;
+
+	// pages of a document may differ in size, depending on the chosen frame
+	if (img.width(), img.height()) != (image.width, image.height) {
+		image.width = img.width();
+		image.height = img.height();
+		image.save(db).await?;
+	}
+
+	let mut new_files = save_image_thumbnails(db, image.id, img, settings).await?;
+	if image.metadata.video.is_some() {
+		let original = ImageFile::get_original(db, image.id)
+			.await?
+			.ok_or(Error::NotFound("original image file".into()))?;
+		new_files.extend(save_video_keyframes(db, image.id, &original.get_path(), settings).await?);
+	}
+
+	// remove thumbnails and keyframes that were not overwritten
+	for old_file in old_files {
+		if !matches!(
+			old_file.kind,
+			ImageFileKind::Thumbnail | ImageFileKind::Keyframe
+		) {
+			continue;
+		}
+
+		let replaced_by = new_files.iter().find(|f| {
+			f.kind == old_file.kind
+				&& f.frame == old_file.frame
+				&& f.width == old_file.width
+				&& f.height == old_file.height
+		});
+
+		match replaced_by {
+			Some(f) if f.extension == old_file.extension => {}
+			Some(_) => remove_derivative(&old_file).await?,
+			None => {
+				remove_derivative(&old_file).await?;
+				old_file.delete_one(db).await?;
+			}
+		}
 	}
 
 	Ok(())
 }
 
+// waits for a running regeneration of the same collection, then uses the settings current by then
+async fn regenerate_collection_thumbnails(db: &Db, collection_id: Uuid) -> Result<()> {
+	let lock = thumbnail_lock(collection_id);
+	let _guard = lock.lock().await;
+
+	measure_time::info_time!("regenerating thumbnails");
+
+	let collection = Collection::get_by_id(db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	let settings = &collection.thumbnail_settings.0;
+	let images = Image::get_all_for_collection(db, collection.id).await?;
+
+	// a failing image is logged and skipped, so the others are still regenerated
+	futures_util::stream::iter(images)
+		.for_each_concurrent(4, |image| async move {
+			let id = image.id;
+			if let Err(e) = regenerate_image_thumbnails(db, image, settings).await {
+				log::error!("could not regenerate thumbnails of {}: {}", id, e);
+			}
+		})
+		.await;
+
+	Collection::mark_images_changed(db, collection.id).await?;
+
+	Ok(())
+}
+
+// regeneration runs in the background, the request returns right away
+pub async fn regenerate_thumbnails(
+	Extension(db): DbExtension,
+	Path(collection_id): Path<Uuid>,
+) -> Result<StatusCode> {
+	Collection::get_by_id(&db, collection_id)
+		.await?
+		.ok_or(Error::NotFound("collection".into()))?;
+
+	tokio::spawn(async move {
+		if let Err(e) = regenerate_collection_thumbnails(&db, collection_id).await {
+			log::error!(
+				"could not regenerate thumbnails of collection {}: {}",
+				collection_id,
+				e
+			);
+		}
+	});
+
+	Ok(StatusCode::ACCEPTED)
+}
+
 pub async fn upload_image(
 	Extension(db): DbExtension,
 	Path(collection_id): Path<Uuid>,
@@ -197,9 +623,14 @@ pub async fn upload_image(
 	let data = data.ok_or(Error::MultipartMissingField("data".into()))?;
 
 	// read image, make sure format is correct
-	let img = ImageReader::new(Cursor::new(&data)).with_guessed_format()?;
-	let format = img.format();
-	let img = img.decode()?;
+	// the file extension is only needed to recognize RAW files and videos
+	let extension = file_name
+		.as_deref()
+		.and_then(|name| std::path::Path::new(name).extension())
+		.and_then(|ext| ext.to_str());
+	let decoded = decode_upload(&data, extension, collection.thumbnail_settings.frame).await?;
+	let duration_ms = decoded.duration_ms();
+	let img = decoded.image;
 
 	// construct new dto for insertion, return metadata
 	let mut image = NewImage {
@@ -210,18 +641,26 @@ pub async fn upload_image(
 	.insert_one(&db)
 	.await?;
 
-	// update metadata; insert original filename
+	// update metadata; insert original filename and the orientation applied to derivatives
 	image.metadata.name = file_name;
+	image.metadata.orientation = decoded.orientation;
+	image.metadata.frame_count = decoded.frames.as_ref().map(|f| f.count);
+	image.metadata.duration_ms = duration_ms;
+	image.metadata.video = decoded.video;
 	image.save(&db).await?;
 
 	// save original version without modifying anything
-	let extension = format.unwrap().extensions_str()[0].to_owned();
 	let image_file = ImageFile {
 		image_id: image.id,
-		width: img.width(),
-		height: img.height(),
-		extension,
+		width: decoded.original_width,
+		height: decoded.original_height,
+		extension: decoded.extension,
 		kind: ImageFileKind::Original,
+		alpha: match img.color().has_alpha() {
+			true => ImageFileAlpha::Kept,
+			false => ImageFileAlpha::Opaque,
+		},
+		frame: 0,
 	};
 	let path = image_file.get_path();
 
@@ -231,12 +670,26 @@ pub async fn upload_image(
 	std::fs::create_dir_all(&dirname)?;
 	let mut writer = BufWriter::new(File::create(path).await?);
 	writer.write_all(&data).await?;
+	writer.flush().await?;
+	let is_video = image.metadata.video.is_some();
+	let original_path = image_file.get_path();
 	image_file.insert_one(&db).await?;
+	Collection::mark_images_changed(&db, collection_id).await?;
 
 	// create and save image versions
-	let meta_ = image.clone();
+	let image_id = image.id;
+	let settings = collection.thumbnail_settings.0;
 	tokio::spawn(async move {
-		let res = save_image_thumbnails(&db.clone(), meta_, img).await;
+		let res = async {
+			save_image_thumbnails(&db, image_id, img, &settings).await?;
+			if is_video {
+				save_video_keyframes(&db, image_id, &original_path, &settings).await?;
+			}
+			Collection::mark_images_changed(&db, collection_id).await?;
+			Ok::<_, Error>(())
+		}
+		.await;
+
 		if let Err(e) = res {
 			log::error!("error during saving image versions: {}", e);
 		}
@@ -256,43 +709,52 @@ pub async fn regenerate_metadata(db: &Db, id: Uuid) -> Result<()> {
 			let extract_image = async move {
 				let mut img = img.lock_owned().await;
 
-				// extract color palette from largest thumbnail size
-				let image_file = ImageFile::get_by_id(
-					db,
-					img.id,
-					img.width,
-					img.height,
-					ImageFileKind::Original,
-				)
-				.await?;
-
-				let image_file = match image_file {
-					None => return Ok::<_, Error>(()),
-					Some(file) => file,
-				};
-
-				let path = image_file.get_path();
-				let reader = std::io::BufReader::new(std::fs::File::open(path)?);
-				let format = image::ImageFormat::from_extension(image_file.extension).unwrap();
-
-				let img_buf = ImageReader::with_format(reader, format).decode()?;
+				// extract color palette from the original
+				let decoded = load_original_decoded(db, img.id, true).await?;
+				img.metadata.frame_count = decoded.frames.as_ref().map(|f| f.count);
+				img.metadata.duration_ms = decoded.duration_ms();
+				img.metadata.video = decoded.video;
+				let img_buf = decoded.image;
 
 				let rgb = img_buf.to_rgb8().into_raw();
 				let palette = color_thief::get_palette(&rgb, color_thief::ColorFormat::Rgb, 10, 3);
 
-				match palette {
-					Ok(palette) => {
-						img.metadata.palette.replace(
-							palette
-								.into_iter()
-								.map(|rgb| (rgb.r, rgb.g, rgb.b))
-								.collect(),
-						);
-					}
+				let palette = match palette {
+					Ok(palette) => palette
+						.into_iter()
+						.map(|rgb| (rgb.r, rgb.g, rgb.b))
+						.collect::<Vec<_>>(),
 					Err(e) => return Err(e.into()),
-				}
-
-				Ok(())
+				};
+				img.metadata.palette.replace(palette.clone());
+
+				// hashes and colour weights are computed from the 500px thumbnail, if there is one,
+				// so they don't depend on the resolution of the original; quality metrics are
+				// computed from the original, as compression artifacts would skew them
+				let thumbnail = ImageFile::get_approximate_size(db, img.id, 500, 500).await?;
+				let (hashes, quality, lab_palette, histogram) =
+					tokio::task::spawn_blocking(move || {
+						let quality = QualityMetrics::new(&img_buf);
+						let img_buf = match thumbnail {
+							Some(file) if file.kind == ImageFileKind::Thumbnail => {
+								image::open(file.get_path())?
+							}
+							_ => img_buf,
+						};
+						Ok::<_, Error>((
+							PerceptualHashes::new(&img_buf),
+							quality,
+							color::lab_palette(&img_buf, &palette),
+							color::color_histogram(&img_buf),
+						))
+					})
+					.await??;
+				img.metadata.hashes = Some(hashes);
+				img.metadata.quality = Some(quality);
+				img.metadata.lab_palette = Some(lab_palette);
+				img.metadata.color_histogram = Some(histogram);
+
+				Ok::<_, Error>(())
 			}
 			.await;
 
@@ -303,18 +765,12 @@ pub async fn regenerate_metadata(db: &Db, id: Uuid) -> Result<()> {
 			let img = img_multiref.clone();
 			let extract_exif = async move {
 				let mut img = img.lock_owned().await;
-				let image_file = ImageFile::get_by_id(
-					db,
-					img.id,
-					img.width,
-					img.height,
-					ImageFileKind::Original,
-				)
-				.await?;
+				let image_file = ImageFile::get_original(db, img.id).await?;
 
+				// videos don't carry EXIF data
 				let image_file = match image_file {
-					None => return Ok::<_, Error>(()),
-					Some(file) => file,
+					Some(file) if !video::is_video_extension(&file.extension) => file,
+					_ => return Ok::<_, Error>(()),
 				};
 
 				let mut reader = BufReader::new(File::open(image_file.get_path()).await?);
@@ -328,6 +784,16 @@ pub async fn regenerate_metadata(db: &Db, id: Uuid) -> Result<()> {
 				match exif {
 					Err(e) => return Err(e.into()),
 					Ok(exif) => {
+						// derivatives are oriented, so are the stored dimensions
+						let orientation = read_orientation(&exif);
+						let (width, height) = Orientation::oriented_size(
+							orientation,
+							(image_file.width, image_file.height),
+						);
+						img.metadata.orientation = orientation;
+						img.width = width;
+						img.height = height;
+
 						for f in exif.fields() {
 							let tag = format!("{}", f.tag);
 							let val = format!("{}", f.display_value());
@@ -336,19 +802,12 @@ pub async fn regenerate_metadata(db: &Db, id: Uuid) -> Result<()> {
 								.exif
 								.get_or_insert(Default::default())
 								.insert(tag, val);
+						}
 
-							match f.tag {
-								exif::Tag::DateTime => {
-									match chrono::NaiveDateTime::parse_from_str(
-										&format!("{}", f.display_value()),
-										"%Y-%m-%d %H:%M:%S",
-									) {
-										Ok(date_time) => img.metadata.date_time = Some(date_time),
-										Err(_) => {}
-									}
-								}
-								_ => {}
-							}
+						img.metadata.capture = Some(CaptureInfo::from_exif(&exif));
+						img.metadata.gps = GpsPosition::from_exif(&exif);
+						if let Some(date_time) = read_date_time(&exif) {
+							img.metadata.date_time = Some(date_time);
 						}
 					}
 				}
@@ -367,6 +826,9 @@ pub async fn regenerate_metadata(db: &Db, id: Uuid) -> Result<()> {
 		})
 		.await?;
 
+	// layouts computed from the previous metadata are outdated
+	Collection::mark_images_changed(db, id).await?;
+
 	Ok(())
 }
 
@@ -378,8 +840,9 @@ pub async fn finalize_collection(
 		.await?
 		.ok_or(Error::NotFound("collection".into()))?;
 
-	regenerate_static_atlas(&db, id).await?;
+	// metadata first, as it marks the atlas stale
 	regenerate_metadata(&db, id).await?;
+	regenerate_static_atlas(&db, id).await?;
 
 	collection.finalized = true;
 	collection.save(&db).await?;
@@ -438,5 +901,7 @@ pub async fn duplicate(Extension(db): DbExtension, Path(collection_id): Path<Uui
 		})
 		.await?;
 
+	Collection::mark_images_changed(&db, collection_id).await?;
+
 	Ok(())
 }
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use image::DynamicImage;
+use tokio::process::Command;
+
+use crate::db::FrameSelection;
+use crate::decode::{DecodedImage, FrameInfo};
+use crate::err::{Error, Result};
+
+// video containers, recognized by extension
+pub const VIDEO_EXTENSIONS: &[&str] = &["avi", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "webm"];
+
+// number of keyframes extracted from every video, spread evenly over its duration
+pub const KEYFRAME_COUNT: u32 = 5;
+
+pub fn is_video_extension(extension: &str) -> bool {
+	VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct VideoMetadata {
+	pub duration_ms: u64,
+	// resolution of the video stream, before rotation
+	pub width: u32,
+	pub height: u32,
+	pub codec: String,
+	// keyframes extracted as thumbnails of the video
+	pub keyframe_count: u32,
+}
+
+// paths to the binaries can be set, otherwise they are looked up in PATH
+fn ffmpeg() -> Command {
+	Command::new(dotenv::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".into()))
+}
+
+fn ffprobe() -> Command {
+	Command::new(dotenv::var("FFPROBE_PATH").unwrap_or_else(|_| "ffprobe".into()))
+}
+
+async fn run(mut command: Command) -> Result<Vec<u8>> {
+	let output = command.kill_on_drop(true).output().await?;
+	if !output.status.success() {
+		return Err(Error::VideoToolError(
+			String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+		));
+	}
+
+	Ok(output.stdout)
+}
+
+#[derive(serde::Deserialize)]
+struct ProbeStream {
+	codec_name: String,
+	width: u32,
+	height: u32,
+	// only known if the container stores it, "N/A" otherwise
+	nb_frames: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProbeFormat {
+	duration: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProbeOutput {
+	streams: Vec<ProbeStream>,
+	format: ProbeFormat,
+}
+
+// metadata and the number of frames, if the container stores it
+pub async fn probe(path: &Path) -> Result<(VideoMetadata, Option<u32>)> {
+	let mut command = ffprobe();
+	command
+		.args(["-v", "error", "-select_streams", "v:0"])
+		.args([
+			"-show_entries",
+			"stream=codec_name,width,height,nb_frames:format=duration",
+		])
+		.args(["-of", "json"])
+		.arg(path);
+
+	let output: ProbeOutput = serde_json::from_slice(&run(command).await?)?;
+	let stream = output
+		.streams
+		.into_iter()
+		.next()
+		.ok_or(Error::VideoToolError("no video stream".into()))?;
+
+	// containers without a duration are treated like a still image
+	let duration = output
+		.format
+		.duration
+		.and_then(|d| d.parse::<f64>().ok())
+		.unwrap_or(0.0);
+
+	let frame_count = stream.nb_frames.and_then(|n| n.parse::<u32>().ok());
+
+	Ok((
+		VideoMetadata {
+			duration_ms: (duration * 1000.0) as u64,
+			width: stream.width,
+			height: stream.height,
+			codec: stream.codec_name,
+			keyframe_count: KEYFRAME_COUNT,
+		},
+		frame_count,
+	))
+}
+
+// timestamp of keyframe n; the middles of evenly sized sections, skipping fades at either end
+pub fn keyframe_timestamp_ms(video: &VideoMetadata, n: u32) -> u64 {
+	video.duration_ms * (2 * n as u64 + 1) / (2 * KEYFRAME_COUNT as u64)
+}
+
+// decode the frame shown at the given timestamp, rotated as the video is displayed
+pub async fn extract_frame(path: &Path, timestamp_ms: u64) -> Result<DynamicImage> {
+	let mut command = ffmpeg();
+	command
+		.args(["-v", "error", "-ss"])
+		.arg(format!(
+			"{}.{:03}",
+			timestamp_ms / 1000,
+			timestamp_ms % 1000
+		))
+		.arg("-i")
+		.arg(path)
+		.args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"]);
+
+	let data = run(command).await?;
+	if data.is_empty() {
+		return Err(Error::VideoToolError(format!(
+			"no frame at {} ms",
+			timestamp_ms
+		)));
+	}
+
+	Ok(image::load_from_memory_with_format(
+		&data,
+		image::ImageFormat::Png,
+	)?)
+}
+
+// decode the poster frame of a video, the keyframe chosen by the collection settings
+pub async fn decode_video(
+	path: &Path,
+	extension: &str,
+	frame: FrameSelection,
+) -> Result<DecodedImage> {
+	let (video, frame_count) = probe(path).await?;
+	let n = frame.index(video.keyframe_count);
+	let image = extract_frame(path, keyframe_timestamp_ms(&video, n)).await?;
+
+	Ok(DecodedImage {
+		original_width: image.width(),
+		original_height: image.height(),
+		image,
+		extension: extension.to_lowercase(),
+		orientation: None,
+		frames: frame_count.map(|count| FrameInfo {
+			count,
+			duration_ms: Some(video.duration_ms),
+		}),
+		video: Some(video),
+	})
+}